@@ -0,0 +1,747 @@
+//! Textures, models and shaders loaded by path and looked up by handle.
+//!
+//! Loading a path that is already loaded (with the same options) returns
+//! the existing handle instead of reading the file again. Every load and
+//! `Assets::retain` counts as a reference, and an asset is dropped once
+//! `Assets::release` was called as often.
+//!
+//! After `Assets::watch`, textures, models and scenes are reloaded when
+//! their files change on disk, see `Assets::reload_changed`.
+
+use std::{
+    collections::HashSet,
+    hash::Hash,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::mpsc,
+};
+
+use notify::Watcher as _;
+
+use crate::{
+    context::Context,
+    error::Result,
+    model::Model,
+    scene::Scene,
+    texture::{Texture, TextureOptions},
+};
+
+use storage::Storage;
+
+/// Refers to an asset of type `T` in `Assets`. Handles are never reused,
+/// not even across `Assets`, so one that outlived its asset or belongs to
+/// another `Assets` doesn't find a different asset.
+pub struct Handle<T> {
+    id: u32,
+    _asset: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(id: u32) -> Self {
+        Self {
+            id,
+            _asset: PhantomData,
+        }
+    }
+}
+
+// Derives would require T to implement the traits as well
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle<{}>({})", std::any::type_name::<T>(), self.id)
+    }
+}
+
+// Public in a private module, so `Asset` can name it without exposing it
+mod storage {
+    use std::{
+        collections::HashMap,
+        hash::Hash,
+        path::PathBuf,
+        sync::atomic::{AtomicU32, Ordering},
+    };
+
+    use super::Handle;
+    use crate::error::Result;
+
+    pub struct Entry<K, T> {
+        asset: T,
+        /// What the asset was loaded from, `None` for assets added directly
+        key: Option<K>,
+        /// Every file the asset was read from, if it was read from more
+        /// than its key
+        files: Vec<PathBuf>,
+        refs: u32,
+    }
+
+    /// Shared by every storage, so handle ids are unique in the process.
+    static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+    /// The assets of one type, along with the keys they were loaded from.
+    pub struct Storage<K, T> {
+        entries: HashMap<u32, Entry<K, T>>,
+        by_key: HashMap<K, u32>,
+    }
+
+    impl<K: Hash + Eq + Clone, T> Default for Storage<K, T> {
+        fn default() -> Self {
+            Self {
+                entries: HashMap::new(),
+                by_key: HashMap::new(),
+            }
+        }
+    }
+
+    impl<K: Hash + Eq + Clone, T> Storage<K, T> {
+        /// The handle of the asset loaded from `key`, with one more reference,
+        /// or the one `load` creates if there is none yet.
+        pub fn get_or_load(&mut self, key: K, load: impl FnOnce() -> Result<T>) -> Result<Handle<T>> {
+            match self.find(&key) {
+                Some(handle) => Ok(handle),
+                None => Ok(self.insert(load()?, Some(key))),
+            }
+        }
+
+        /// The handle of the asset loaded from `key`, with one more reference.
+        pub fn find(&mut self, key: &K) -> Option<Handle<T>> {
+            let handle = Handle::new(*self.by_key.get(key)?);
+            self.retain(handle);
+            Some(handle)
+        }
+
+        pub fn insert(&mut self, asset: T, key: Option<K>) -> Handle<T> {
+            let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+            if let Some(key) = &key {
+                self.by_key.insert(key.clone(), id);
+            }
+            let entry = Entry {
+                asset,
+                key,
+                files: Vec::new(),
+                refs: 1,
+            };
+            self.entries.insert(id, entry);
+            Handle::new(id)
+        }
+
+        pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+            self.entries.get(&handle.id).map(|entry| &entry.asset)
+        }
+
+        pub fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+            self.entries.get_mut(&handle.id).map(|entry| &mut entry.asset)
+        }
+
+        pub fn retain(&mut self, handle: Handle<T>) -> bool {
+            match self.entries.get_mut(&handle.id) {
+                Some(entry) => {
+                    entry.refs += 1;
+                    true
+                }
+                None => false,
+            }
+        }
+
+        pub fn release(&mut self, handle: Handle<T>) -> Option<T> {
+            let entry = self.entries.get_mut(&handle.id)?;
+            entry.refs -= 1;
+            if entry.refs > 0 {
+                return None;
+            }
+            let entry = self.entries.remove(&handle.id)?;
+            if let Some(key) = &entry.key {
+                self.by_key.remove(key);
+            }
+            Some(entry.asset)
+        }
+
+        pub fn ref_count(&self, handle: Handle<T>) -> u32 {
+            self.entries.get(&handle.id).map_or(0, |entry| entry.refs)
+        }
+
+        pub fn key(&self, handle: Handle<T>) -> Option<&K> {
+            self.entries.get(&handle.id)?.key.as_ref()
+        }
+
+        pub fn files(&self, handle: Handle<T>) -> &[PathBuf] {
+            self.entries.get(&handle.id).map_or(&[], |entry| &entry.files)
+        }
+
+        pub fn set_files(&mut self, handle: Handle<T>, files: Vec<PathBuf>) {
+            if let Some(entry) = self.entries.get_mut(&handle.id) {
+                entry.files = files;
+            }
+        }
+
+        /// Every asset that was loaded from a key.
+        pub fn keys(&self) -> impl Iterator<Item = (Handle<T>, &K)> {
+            self.by_key.iter().map(|(key, &id)| (Handle::new(id), key))
+        }
+    }
+}
+
+/// Types that live in `Assets`, with what they are loaded from.
+pub trait Asset: Sized + 'static {
+    type Key: Hash + Eq + Clone;
+
+    #[doc(hidden)]
+    fn storage(assets: &Assets) -> &Storage<Self::Key, Self>;
+    #[doc(hidden)]
+    fn storage_mut(assets: &mut Assets) -> &mut Storage<Self::Key, Self>;
+}
+
+impl Asset for Texture {
+    /// The file of every layer, and how they were uploaded
+    type Key = (Vec<PathBuf>, TextureOptions);
+
+    fn storage(assets: &Assets) -> &Storage<Self::Key, Self> {
+        &assets.textures
+    }
+
+    fn storage_mut(assets: &mut Assets) -> &mut Storage<Self::Key, Self> {
+        &mut assets.textures
+    }
+}
+
+impl Asset for Model {
+    type Key = PathBuf;
+
+    fn storage(assets: &Assets) -> &Storage<Self::Key, Self> {
+        &assets.models
+    }
+
+    fn storage_mut(assets: &mut Assets) -> &mut Storage<Self::Key, Self> {
+        &mut assets.models
+    }
+}
+
+impl Asset for Scene {
+    type Key = PathBuf;
+
+    fn storage(assets: &Assets) -> &Storage<Self::Key, Self> {
+        &assets.scenes
+    }
+
+    fn storage_mut(assets: &mut Assets) -> &mut Storage<Self::Key, Self> {
+        &mut assets.scenes
+    }
+}
+
+impl Asset for wgpu::ShaderModule {
+    type Key = PathBuf;
+
+    fn storage(assets: &Assets) -> &Storage<Self::Key, Self> {
+        &assets.shaders
+    }
+
+    fn storage_mut(assets: &mut Assets) -> &mut Storage<Self::Key, Self> {
+        &mut assets.shaders
+    }
+}
+
+/// An asset that `Assets::reload_changed` replaced with the new version
+/// of its file. Its handle stays the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reloaded {
+    Texture(Handle<Texture>),
+    Model(Handle<Model>),
+    Scene(Handle<Scene>),
+}
+
+/// Watches the folders of the loaded files.
+struct Watcher {
+    watcher: notify::RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    folders: HashSet<PathBuf>,
+}
+
+impl Watcher {
+    fn watch(&mut self, file: &Path) -> Result<()> {
+        // Folders rather than files, so files that are saved by replacing
+        // them are still watched afterwards
+        let folder = file.parent().unwrap_or(file);
+        if !self.folders.contains(folder) {
+            self.watcher.watch(folder, notify::RecursiveMode::NonRecursive)?;
+            self.folders.insert(folder.to_path_buf());
+        }
+        Ok(())
+    }
+
+    /// The files that were written to since the last call.
+    fn changed_files(&self) -> HashSet<PathBuf> {
+        let mut changed = HashSet::new();
+        for event in self.events.try_iter() {
+            match event {
+                Ok(event) if event.kind.is_create() || event.kind.is_modify() => changed.extend(event.paths),
+                Ok(_) => {}
+                Err(e) => log::warn!("Could not watch the assets: {}", e),
+            }
+        }
+        changed
+    }
+}
+
+#[derive(Default)]
+pub struct Assets {
+    textures: Storage<(Vec<PathBuf>, TextureOptions), Texture>,
+    models: Storage<PathBuf, Model>,
+    scenes: Storage<PathBuf, Scene>,
+    shaders: Storage<PathBuf, wgpu::ShaderModule>,
+    watcher: Option<Watcher>,
+}
+
+impl Assets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads an image as a texture, or returns the texture already loaded
+    /// from the same file with the same options.
+    pub fn load_texture<P: AsRef<Path>>(
+        &mut self,
+        context: &Context,
+        path: P,
+        options: &TextureOptions,
+    ) -> Result<Handle<Texture>> {
+        self.load_texture_layers(context, &[path], options)
+    }
+
+    /// Loads images as the layers of one texture with
+    /// `Texture::from_layers`, or returns the texture already loaded from
+    /// the same files in the same order.
+    pub fn load_texture_layers<P: AsRef<Path>>(
+        &mut self,
+        context: &Context,
+        paths: &[P],
+        options: &TextureOptions,
+    ) -> Result<Handle<Texture>> {
+        let paths = paths
+            .iter()
+            .map(std::fs::canonicalize)
+            .collect::<std::io::Result<Vec<_>>>()?;
+        for path in &paths {
+            self.watch_file(path)?;
+        }
+        self.textures
+            .get_or_load((paths.clone(), *options), || read_texture(context, &paths, options))
+    }
+
+    /// Loads an OBJ model with `Model::load_obj`, or returns the one
+    /// already loaded from the same file. `layout` is the texture bind
+    /// group layout.
+    pub fn load_model<P: AsRef<Path>>(
+        &mut self,
+        context: &Context,
+        layout: &wgpu::BindGroupLayout,
+        path: P,
+    ) -> Result<Handle<Model>> {
+        let path = std::fs::canonicalize(path)?;
+        let load = |path: &Path| Model::load_obj(context, layout, path);
+        self.load_with_files(path, load, Model::obj_files)
+    }
+
+    /// Imports a glTF file with `Scene::load_gltf`, or returns the scene
+    /// already imported from the same file. `layout` is the PBR material
+    /// layout.
+    pub fn load_scene<P: AsRef<Path>>(
+        &mut self,
+        context: &Context,
+        layout: &wgpu::BindGroupLayout,
+        path: P,
+    ) -> Result<Handle<Scene>> {
+        let path = std::fs::canonicalize(path)?;
+        let load = |path: &Path| Scene::load_gltf(context, layout, path);
+        self.load_with_files(path, load, Scene::gltf_files)
+    }
+
+    /// Compiles a WGSL file, or returns the module already compiled from
+    /// the same file.
+    pub async fn load_shader<P: AsRef<Path>>(
+        &mut self,
+        context: &Context,
+        path: P,
+    ) -> Result<Handle<wgpu::ShaderModule>> {
+        let path = std::fs::canonicalize(path)?;
+        if let Some(handle) = self.shaders.find(&path) {
+            return Ok(handle);
+        }
+        let source = std::fs::read_to_string(&path)?;
+        let module = context
+            .create_shader_module(&path.to_string_lossy(), &source)
+            .await?;
+        Ok(self.shaders.insert(module, Some(path)))
+    }
+
+    /// Adds an asset that wasn't loaded from a file, with one reference.
+    pub fn add<T: Asset>(&mut self, asset: T) -> Handle<T> {
+        T::storage_mut(self).insert(asset, None)
+    }
+
+    pub fn get<T: Asset>(&self, handle: Handle<T>) -> Option<&T> {
+        T::storage(self).get(handle)
+    }
+
+    pub fn get_mut<T: Asset>(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        T::storage_mut(self).get_mut(handle)
+    }
+
+    /// Adds a reference to an asset. Returns false if it was dropped.
+    pub fn retain<T: Asset>(&mut self, handle: Handle<T>) -> bool {
+        T::storage_mut(self).retain(handle)
+    }
+
+    /// Removes a reference to an asset. Returns the asset if that was the
+    /// last one, after which the handle no longer finds it.
+    pub fn release<T: Asset>(&mut self, handle: Handle<T>) -> Option<T> {
+        T::storage_mut(self).release(handle)
+    }
+
+    /// The number of references to an asset, 0 once it was dropped.
+    pub fn ref_count<T: Asset>(&self, handle: Handle<T>) -> u32 {
+        T::storage(self).ref_count(handle)
+    }
+
+    /// What the asset was loaded from, or `None` if it was added directly.
+    pub fn key<T: Asset>(&self, handle: Handle<T>) -> Option<&T::Key> {
+        T::storage(self).key(handle)
+    }
+
+    /// Starts watching the files of the loaded textures, models and
+    /// scenes, and of those loaded later on, for `reload_changed`.
+    pub fn watch(&mut self) -> Result<()> {
+        if self.watcher.is_some() {
+            return Ok(());
+        }
+        let (sender, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(move |event| {
+            // Only fails once the assets are dropped
+            let _ = sender.send(event);
+        })?;
+        self.watcher = Some(Watcher {
+            watcher,
+            events,
+            folders: HashSet::new(),
+        });
+        let files = self
+            .textures
+            .keys()
+            .flat_map(|(_, (paths, _))| paths.clone())
+            .chain(self.models.keys().flat_map(|(handle, _)| self.models.files(handle).to_vec()))
+            .chain(self.scenes.keys().flat_map(|(handle, _)| self.scenes.files(handle).to_vec()))
+            .collect::<Vec<_>>();
+        for file in files {
+            self.watch_file(&file)?;
+        }
+        Ok(())
+    }
+
+    /// Reloads the textures, models and scenes whose files changed since
+    /// the last call, keeping their handles. `texture_layout` and
+    /// `pbr_layout` are the layouts `load_model` and `load_scene` take.
+    ///
+    /// Models and scenes are reloaded when one of the files they read
+    /// changes, see `Model::obj_files` and `Scene::gltf_files`. Files that
+    /// fail to load, e.g. because they are only partly written, are logged
+    /// as warnings and the previous version is kept. Bind groups created
+    /// from the reloaded textures have to be created again.
+    pub fn reload_changed(
+        &mut self,
+        context: &Context,
+        texture_layout: &wgpu::BindGroupLayout,
+        pbr_layout: &wgpu::BindGroupLayout,
+    ) -> Vec<Reloaded> {
+        let changed = match &self.watcher {
+            Some(watcher) => watcher.changed_files(),
+            None => return Vec::new(),
+        };
+        if changed.is_empty() {
+            return Vec::new();
+        }
+
+        let mut reloaded = Vec::new();
+        let textures = self
+            .textures
+            .keys()
+            .filter(|(_, (paths, _))| paths.iter().any(|path| changed.contains(path)))
+            .map(|(handle, key)| (handle, key.clone()))
+            .collect::<Vec<_>>();
+        for (handle, (paths, options)) in textures {
+            match read_texture(context, &paths, &options) {
+                Ok(texture) => {
+                    *self.textures.get_mut(handle).unwrap() = texture;
+                    reloaded.push(Reloaded::Texture(handle));
+                }
+                Err(e) => log::warn!("Could not reload {}: {}", paths[0].display(), e),
+            }
+        }
+
+        let models = self.reload_dependents(
+            &changed,
+            |path| Model::load_obj(context, texture_layout, path),
+            Model::obj_files,
+        );
+        reloaded.extend(models.into_iter().map(Reloaded::Model));
+        let scenes = self.reload_dependents(
+            &changed,
+            |path| Scene::load_gltf(context, pbr_layout, path),
+            Scene::gltf_files,
+        );
+        reloaded.extend(scenes.into_iter().map(Reloaded::Scene));
+        reloaded
+    }
+
+    /// `Storage::get_or_load` for models and scenes, which read more files
+    /// than `path`. `files` lists them to be watched.
+    fn load_with_files<T: Asset<Key = PathBuf>>(
+        &mut self,
+        path: PathBuf,
+        load: impl FnOnce(&Path) -> Result<T>,
+        files: fn(&Path) -> Result<Vec<PathBuf>>,
+    ) -> Result<Handle<T>> {
+        if let Some(handle) = T::storage_mut(self).find(&path) {
+            return Ok(handle);
+        }
+        let asset = load(&path)?;
+        let files = self.watch_files(&path, files)?;
+        let storage = T::storage_mut(self);
+        let handle = storage.insert(asset, Some(path));
+        storage.set_files(handle, files);
+        Ok(handle)
+    }
+
+    /// Reloads the assets of type `T` that read one of the changed files.
+    fn reload_dependents<T: Asset<Key = PathBuf>>(
+        &mut self,
+        changed: &HashSet<PathBuf>,
+        load: impl Fn(&Path) -> Result<T>,
+        files: fn(&Path) -> Result<Vec<PathBuf>>,
+    ) -> Vec<Handle<T>> {
+        let storage = T::storage(self);
+        let stale = storage
+            .keys()
+            .filter(|&(handle, _)| storage.files(handle).iter().any(|file| changed.contains(file)))
+            .map(|(handle, path)| (handle, path.clone()))
+            .collect::<Vec<_>>();
+        let mut reloaded = Vec::new();
+        for (handle, path) in stale {
+            // The new version may read other files
+            match load(&path).and_then(|asset| Ok((asset, self.watch_files(&path, files)?))) {
+                Ok((asset, files)) => {
+                    let storage = T::storage_mut(self);
+                    *storage.get_mut(handle).unwrap() = asset;
+                    storage.set_files(handle, files);
+                    reloaded.push(handle);
+                }
+                Err(e) => log::warn!("Could not reload {}: {}", path.display(), e),
+            }
+        }
+        reloaded
+    }
+
+    /// The files `files` lists for `path`, watched and resolved like the
+    /// paths of file events.
+    fn watch_files(
+        &mut self,
+        path: &Path,
+        files: fn(&Path) -> Result<Vec<PathBuf>>,
+    ) -> Result<Vec<PathBuf>> {
+        let files = files(path)?
+            .into_iter()
+            .map(|file| std::fs::canonicalize(&file).unwrap_or(file))
+            .collect::<Vec<_>>();
+        for file in &files {
+            self.watch_file(file)?;
+        }
+        Ok(files)
+    }
+
+    fn watch_file(&mut self, file: &Path) -> Result<()> {
+        match &mut self.watcher {
+            Some(watcher) => watcher.watch(file),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reads images and uploads them as the layers of one texture.
+fn read_texture(
+    context: &Context,
+    paths: &[PathBuf],
+    options: &TextureOptions,
+) -> Result<Texture> {
+    let layers = paths
+        .iter()
+        .map(|path| Ok(image::load_from_memory(&std::fs::read(path)?)?))
+        .collect::<Result<Vec<_>>>()?;
+    let label = paths.first().map(|path| path.to_string_lossy());
+    Texture::from_layers(context, &layers, label.as_deref(), options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_are_shared_until_released() {
+        let mut storage = Storage::<&str, u32>::default();
+        let mut loads = 0;
+        let mut load = |storage: &mut Storage<&str, u32>, key| {
+            storage.get_or_load(key, || {
+                loads += 1;
+                Ok(loads)
+            })
+        };
+        let a = load(&mut storage, "a").unwrap();
+        let b = load(&mut storage, "b").unwrap();
+        assert_eq!(load(&mut storage, "a").unwrap(), a);
+        assert_ne!(a, b);
+        assert_eq!(storage.ref_count(a), 2);
+
+        assert_eq!(storage.release(a), None);
+        assert_eq!(storage.release(a), Some(1));
+        assert_eq!(storage.get(a), None);
+        assert!(!storage.retain(a));
+
+        // Loaded again after being dropped, with a new handle
+        let c = load(&mut storage, "a").unwrap();
+        assert_ne!(c, a);
+        assert_eq!(storage.get(c), Some(&3));
+        assert_eq!(storage.get(b), Some(&2));
+    }
+
+    #[test]
+    fn textures_are_deduplicated_by_file_and_options() {
+        let context = pollster::block_on(Context::new_headless()).unwrap();
+        let mut assets = Assets::new();
+        let tree = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/tree.png");
+        let same_tree = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/../assets/tree.png");
+        let load = |assets: &mut Assets, path, options| {
+            assets
+                .load_texture(&context, path, &options)
+                .unwrap()
+        };
+        let first = load(&mut assets, tree, TextureOptions::default());
+        assert_eq!(load(&mut assets, same_tree, TextureOptions::default()), first);
+        assert_ne!(load(&mut assets, tree, TextureOptions::pixel_art()), first);
+        assert_eq!(assets.ref_count(first), 2);
+    }
+
+    /// Removes the folder even when an assertion fails.
+    struct TempFolder(PathBuf);
+
+    impl TempFolder {
+        fn new(name: &str) -> Self {
+            let folder = std::env::temp_dir().join(format!("learning_wgpu-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&folder).unwrap();
+            Self(folder)
+        }
+    }
+
+    impl Drop for TempFolder {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Calls `reload_changed` until `expected` was reloaded, and returns
+    /// everything that was. The events arrive on another thread, possibly
+    /// spread over several calls.
+    fn reload_until(context: &Context, assets: &mut Assets, expected: Reloaded) -> Vec<Reloaded> {
+        let texture_layout = context
+            .device
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor { label: None, entries: &[] });
+        let pbr_layout = crate::pbr::PbrMaterial::bind_group_layout(&context.device);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        let mut reloaded = Vec::new();
+        while !reloaded.contains(&expected) {
+            assert!(std::time::Instant::now() < deadline, "{:?} wasn't reloaded", expected);
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            reloaded.extend(assets.reload_changed(context, &texture_layout, &pbr_layout));
+        }
+        reloaded
+    }
+
+    #[test]
+    fn changed_textures_are_reloaded() {
+        let context = pollster::block_on(Context::new_headless()).unwrap();
+        let folder = TempFolder::new("reload-textures");
+        let save = |name, color| {
+            let path = folder.0.join(name);
+            image::RgbaImage::from_pixel(2, 2, image::Rgba(color)).save(&path).unwrap();
+            path
+        };
+        let path = save("sprite.png", [255, 0, 0, 255]);
+        let marker_path = save("marker.png", [0, 0, 0, 255]);
+
+        let mut assets = Assets::new();
+        let load = |assets: &mut Assets, path| {
+            assets
+                .load_texture(&context, path, &TextureOptions::default())
+                .unwrap()
+        };
+        let texture = load(&mut assets, &path);
+        let marker = load(&mut assets, &marker_path);
+        assets.watch().unwrap();
+
+        save("sprite.png", [0, 255, 0, 255]);
+        reload_until(&context, &mut assets, Reloaded::Texture(texture));
+
+        // A file that fails to decode keeps the previous texture. The marker
+        // is written afterwards, so its events come after those of the file
+        std::fs::write(&path, b"not a png").unwrap();
+        save("marker.png", [255; 4]);
+        let reloaded = reload_until(&context, &mut assets, Reloaded::Texture(marker));
+        assert!(!reloaded.contains(&Reloaded::Texture(texture)));
+        assert!(assets.get(texture).is_some());
+    }
+
+    #[test]
+    fn scenes_are_reloaded_when_their_textures_change() {
+        let context = pollster::block_on(Context::new_headless()).unwrap();
+        let folder = TempFolder::new("reload-scenes");
+        let assets_folder = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        for name in ["quads.gltf", "dirt.png", "tree.png"] {
+            std::fs::copy(assets_folder.join(name), folder.0.join(name)).unwrap();
+        }
+
+        let mut assets = Assets::new();
+        let layout = crate::pbr::PbrMaterial::bind_group_layout(&context.device);
+        let scene = assets
+            .load_scene(&context, &layout, folder.0.join("quads.gltf"))
+            .unwrap();
+        let marker = assets
+            .load_texture(&context, folder.0.join("tree.png"), &TextureOptions::default())
+            .unwrap();
+        assets.watch().unwrap();
+
+        // The scene doesn't read the marker, so it is left alone
+        std::fs::copy(assets_folder.join("dirt.png"), folder.0.join("tree.png")).unwrap();
+        let reloaded = reload_until(&context, &mut assets, Reloaded::Texture(marker));
+        assert!(!reloaded.contains(&Reloaded::Scene(scene)));
+
+        std::fs::copy(assets_folder.join("tree.png"), folder.0.join("dirt.png")).unwrap();
+        reload_until(&context, &mut assets, Reloaded::Scene(scene));
+    }
+}
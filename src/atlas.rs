@@ -0,0 +1,311 @@
+//! Many small images packed into one texture, so meshes using any of them
+//! can share a single bind group.
+//!
+//! Sprites are placed on shelves, tallest first, in the smallest square
+//! power of two atlas they fit in. Each one is surrounded by `padding`
+//! texels repeating its edge, so linear filtering at the edge of a sprite
+//! never reads its neighbours.
+//!
+//! Atlases have no mip levels: smaller levels average texels of
+//! neighbouring sprites unless the padding grows with every level and the
+//! sprites are aligned to it.
+
+use std::collections::HashMap;
+
+use crate::{
+    context::Context,
+    error::{Error, Result},
+    shapes::MeshData,
+    texture::{Mipmaps, Texture, TextureOptions},
+};
+
+/// Where a sprite is in the atlas, in texture coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UvRect {
+    /// Top left
+    pub min: [f32; 2],
+    /// Bottom right
+    pub max: [f32; 2],
+}
+
+impl UvRect {
+    /// Maps texture coordinates of the sprite on its own (0 to 1) into the
+    /// atlas.
+    pub fn map(&self, uv: [f32; 2]) -> [f32; 2] {
+        [
+            self.min[0] + (self.max[0] - self.min[0]) * uv[0],
+            self.min[1] + (self.max[1] - self.min[1]) * uv[1],
+        ]
+    }
+
+    /// Maps every texture coordinate of `mesh` into the atlas, so it shows
+    /// the sprite where it would show a whole texture.
+    pub fn apply(&self, mesh: &mut MeshData) {
+        for vertex in &mut mesh.vertices {
+            vertex.uv = self.map(vertex.uv);
+        }
+    }
+}
+
+/// A sprite's place in the atlas in texels, without the padding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Placement {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+/// Collects named images and packs them into an `Atlas`.
+pub struct AtlasBuilder {
+    sprites: Vec<(String, image::RgbaImage)>,
+    padding: u32,
+    max_size: u32,
+}
+
+impl Default for AtlasBuilder {
+    fn default() -> Self {
+        Self {
+            sprites: Vec::new(),
+            padding: 2,
+            // The largest texture WebGL2 limits allow
+            max_size: 2048,
+        }
+    }
+}
+
+impl AtlasBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Texels of repeated edge around every sprite. Linear filtering needs
+    /// 1, the default of 2 leaves room for texture coordinates that are
+    /// slightly off.
+    pub fn with_padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// The width and height the atlas may grow to.
+    pub fn with_max_size(mut self, max_size: u32) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Adds a sprite, replacing any earlier one with the same name.
+    pub fn add(&mut self, name: impl Into<String>, image: &image::DynamicImage) -> &mut Self {
+        let name = name.into();
+        let image = image.to_rgba8();
+        match self.sprites.iter_mut().find(|(sprite, _)| *sprite == name) {
+            Some(sprite) => sprite.1 = image,
+            None => self.sprites.push((name, image)),
+        }
+        self
+    }
+
+    /// Packs the sprites into an image, along with where each one ended up.
+    /// Fails with `Error::AtlasFull` if they don't fit in the maximum size
+    /// and with `Error::EmptySprite` if one of them has no texels.
+    pub fn build_image(&self) -> Result<(image::RgbaImage, HashMap<String, UvRect>)> {
+        let (size, placements) = self.pack()?;
+        let mut atlas = image::RgbaImage::new(size, size);
+        let mut uv_rects = HashMap::new();
+        for ((name, sprite), placement) in self.sprites.iter().zip(placements) {
+            let padding = self.padding as i64;
+            for y in -padding..placement.height as i64 + padding {
+                for x in -padding..placement.width as i64 + padding {
+                    // The padding repeats the nearest texel of the sprite
+                    let source_x = x.clamp(0, placement.width as i64 - 1) as u32;
+                    let source_y = y.clamp(0, placement.height as i64 - 1) as u32;
+                    atlas.put_pixel(
+                        (placement.x as i64 + x) as u32,
+                        (placement.y as i64 + y) as u32,
+                        *sprite.get_pixel(source_x, source_y),
+                    );
+                }
+            }
+            let size = size as f32;
+            uv_rects.insert(
+                name.clone(),
+                UvRect {
+                    min: [placement.x as f32 / size, placement.y as f32 / size],
+                    max: [
+                        (placement.x + placement.width) as f32 / size,
+                        (placement.y + placement.height) as f32 / size,
+                    ],
+                },
+            );
+        }
+        Ok((atlas, uv_rects))
+    }
+
+    /// Packs the sprites and uploads them as one texture. `options.mipmaps`
+    /// is ignored, the texture only has the full size level.
+    pub fn build(
+        &self,
+        context: &Context,
+        label: Option<&str>,
+        options: &TextureOptions,
+    ) -> Result<Atlas> {
+        let (image, sprites) = self.build_image()?;
+        let options = TextureOptions {
+            mipmaps: Mipmaps::None,
+            ..*options
+        };
+        let texture =
+            Texture::from_image_with_options(context, &image::DynamicImage::ImageRgba8(image), label, &options)?;
+        Ok(Atlas { texture, sprites })
+    }
+
+    /// The size of the atlas and the place of every sprite, in the order
+    /// they were added.
+    fn pack(&self) -> Result<(u32, Vec<Placement>)> {
+        // Their padding would have no texels to repeat
+        let empty = self.sprites.iter().find(|(_, sprite)| sprite.width() == 0 || sprite.height() == 0);
+        if let Some((name, _)) = empty {
+            return Err(Error::EmptySprite { name: name.clone() });
+        }
+        let slot = |sprite: &image::RgbaImage| {
+            let (width, height) = sprite.dimensions();
+            (width + self.padding * 2, height + self.padding * 2)
+        };
+        let area = self
+            .sprites
+            .iter()
+            .map(|(_, sprite)| {
+                let (width, height) = slot(sprite);
+                width as u64 * height as u64
+            })
+            .sum::<u64>();
+        let largest = self
+            .sprites
+            .iter()
+            .map(|(_, sprite)| {
+                let (width, height) = slot(sprite);
+                width.max(height)
+            })
+            .max()
+            .unwrap_or(1);
+        let mut size = ((area as f64).sqrt().ceil() as u32).max(largest).next_power_of_two();
+
+        // Tallest first, so the shelves waste little height
+        let mut order = (0..self.sprites.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| std::cmp::Reverse(slot(&self.sprites[i].1).1));
+
+        while size <= self.max_size {
+            if let Some(placements) = self.pack_shelves(size, &order) {
+                return Ok((size, placements));
+            }
+            size *= 2;
+        }
+        Err(Error::AtlasFull {
+            max_size: self.max_size,
+        })
+    }
+
+    /// Fills a `size` x `size` atlas row by row, or returns `None` if the
+    /// sprites don't fit.
+    fn pack_shelves(&self, size: u32, order: &[usize]) -> Option<Vec<Placement>> {
+        let mut placements = vec![None; self.sprites.len()];
+        let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+        for &i in order {
+            let (width, height) = self.sprites[i].1.dimensions();
+            let (slot_width, slot_height) = (width + self.padding * 2, height + self.padding * 2);
+            if x + slot_width > size {
+                x = 0;
+                y += shelf_height;
+                shelf_height = 0;
+            }
+            if x + slot_width > size || y + slot_height > size {
+                return None;
+            }
+            placements[i] = Some(Placement {
+                x: x + self.padding,
+                y: y + self.padding,
+                width,
+                height,
+            });
+            x += slot_width;
+            shelf_height = shelf_height.max(slot_height);
+        }
+        placements.into_iter().collect()
+    }
+}
+
+/// Sprites packed into one texture by `AtlasBuilder`.
+pub struct Atlas {
+    pub texture: Texture,
+    sprites: HashMap<String, UvRect>,
+}
+
+impl Atlas {
+    /// Where the sprite called `name` is, or `None` if there is none.
+    pub fn uv_rect(&self, name: &str) -> Option<UvRect> {
+        self.sprites.get(name).copied()
+    }
+
+    pub fn sprites(&self) -> impl Iterator<Item = (&str, UvRect)> {
+        self.sprites.iter().map(|(name, rect)| (name.as_str(), *rect))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, color: [u8; 4]) -> image::DynamicImage {
+        image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(width, height, image::Rgba(color)))
+    }
+
+    #[test]
+    fn sprites_dont_overlap() {
+        let mut builder = AtlasBuilder::new().with_padding(1);
+        for i in 0..20u32 {
+            builder.add(format!("sprite {}", i), &solid(5 + i % 7 * 3, 4 + i % 5 * 4, [255; 4]));
+        }
+        let (size, placements) = builder.pack().unwrap();
+        let slot = |p: &Placement| (p.x - 1, p.y - 1, p.x + p.width + 1, p.y + p.height + 1);
+        for (i, a) in placements.iter().enumerate() {
+            let (x0, y0, x1, y1) = slot(a);
+            assert!(x1 <= size && y1 <= size);
+            for b in &placements[i + 1..] {
+                let (u0, v0, u1, v1) = slot(b);
+                assert!(x1 <= u0 || u1 <= x0 || y1 <= v0 || v1 <= y0, "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn padding_repeats_the_edges() {
+        let mut sprite = image::RgbaImage::from_pixel(2, 2, image::Rgba([255, 0, 0, 255]));
+        sprite.put_pixel(1, 1, image::Rgba([0, 0, 255, 255]));
+        let mut builder = AtlasBuilder::new().with_padding(2);
+        builder.add("a", &image::DynamicImage::ImageRgba8(sprite));
+        builder.add("b", &solid(3, 1, [0, 255, 0, 255]));
+        let (atlas, uv_rects) = builder.build_image().unwrap();
+
+        let size = atlas.width() as f32;
+        let [x, y] = uv_rects["a"].min.map(|uv| (uv * size) as u32);
+        assert_eq!(atlas.get_pixel(x - 2, y - 2).0, [255, 0, 0, 255]);
+        assert_eq!(atlas.get_pixel(x + 3, y + 3).0, [0, 0, 255, 255]);
+        assert_eq!(atlas.get_pixel(x + 1, y + 3).0, [0, 0, 255, 255]);
+        assert_eq!(uv_rects["b"].max[0] - uv_rects["b"].min[0], 3.0 / size);
+    }
+
+    #[test]
+    fn too_many_sprites_dont_fit() {
+        let mut builder = AtlasBuilder::new().with_max_size(16);
+        builder.add("a", &solid(8, 8, [255; 4]));
+        builder.add("b", &solid(8, 8, [255; 4]));
+        assert!(matches!(builder.pack(), Err(Error::AtlasFull { max_size: 16 })));
+    }
+
+    #[test]
+    fn empty_sprites_are_rejected() {
+        let mut builder = AtlasBuilder::new();
+        builder.add("a", &solid(2, 2, [255; 4]));
+        builder.add("empty", &solid(0, 3, [255; 4]));
+        assert!(matches!(builder.build_image(), Err(Error::EmptySprite { name }) if name == "empty"));
+    }
+}
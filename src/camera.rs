@@ -1,14 +1,93 @@
-use cgmath::{Deg, Vector3, Point3, Matrix4};
-use winit::window::Window;
+use cgmath::{Deg, Vector3, Vector4, Point3, Matrix4};
 
+/// The surface a camera projects onto.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    /// Width in physical pixels
+    pub width: u32,
+    /// Height in physical pixels
+    pub height: u32,
+    /// Physical pixels per logical pixel, as reported by the window
+    pub scale_factor: f64,
+}
+
+impl Viewport {
+    pub fn new(width: u32, height: u32, scale_factor: f64) -> Self {
+        Self {
+            width,
+            height,
+            scale_factor,
+        }
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width as f32 / self.height.max(1) as f32
+    }
+
+    /// Size in logical pixels, which don't change when the window moves to
+    /// a monitor with a different DPI.
+    pub fn logical_size(&self) -> (f32, f32) {
+        let scale = self.scale_factor as f32;
+        (self.width as f32 / scale, self.height as f32 / scale)
+    }
+}
+
+/// The smallest `OrthographicSize` values are clamped to, as zero or
+/// negative ones would make the projection infinite or NaN.
+const MIN_ORTHOGRAPHIC_SIZE: f32 = 1e-4;
+
+/// How much of an orthographic view is visible.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrthographicSize {
+    /// World units visible vertically. The width follows the aspect ratio.
+    Height(f32),
+    /// Logical pixels covered by one world unit, so sprites keep their size
+    /// on screen regardless of the window size.
+    PixelsPerUnit(f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective {
+        /// Field Of View in degress
+        fov: f32,
+    },
+    Orthographic(OrthographicSize),
+}
+
+impl Projection {
+    fn build(&self, viewport: &Viewport, znear: f32, zfar: f32) -> Matrix4<f32> {
+        match *self {
+            Projection::Perspective { fov } => {
+                cgmath::perspective(Deg(fov), viewport.aspect_ratio(), znear, zfar)
+            }
+            Projection::Orthographic(size) => {
+                let (half_width, half_height) = match size {
+                    OrthographicSize::Height(height) => {
+                        let half_height = height.max(MIN_ORTHOGRAPHIC_SIZE) / 2.0;
+                        (half_height * viewport.aspect_ratio(), half_height)
+                    }
+                    OrthographicSize::PixelsPerUnit(ppu) => {
+                        let ppu = ppu.max(MIN_ORTHOGRAPHIC_SIZE);
+                        let (width, height) = viewport.logical_size();
+                        (width / ppu / 2.0, height / ppu / 2.0)
+                    }
+                };
+                cgmath::ortho(-half_width, half_width, -half_height, half_height, znear, zfar)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Camera {
     pub eye: Point3<f32>,
     pub up: Vector3<f32>,
     pub target: Point3<f32>,
-    /// Field Of View in degress
-    pub fov: f32,
-    /// Aspect Ratio
-    pub ratio: f32,
+    pub projection: Projection,
+    /// Surface the camera renders to. `State` keeps it in sync with the
+    /// window, so the projection follows resizes and DPI changes.
+    pub viewport: Viewport,
     // Z near distance
     pub znear: f32,
     // Z far distance
@@ -16,10 +95,53 @@ pub struct Camera {
 }
 
 impl Camera {
-    fn build_view_proj(&self, window: &Window) -> cgmath::Matrix4<f32> {
+    /// Blends the placement of `previous` into this camera, with `alpha`
+    /// going from 0 (all `previous`) to 1 (all `self`).
+    pub fn interpolate(&self, previous: &Camera, alpha: f32) -> Camera {
+        use cgmath::EuclideanSpace;
+        let lerp = |from: Point3<f32>, to: Point3<f32>| {
+            Point3::from_vec(from.to_vec() + (to - from) * alpha)
+        };
+        Camera {
+            eye: lerp(previous.eye, self.eye),
+            target: lerp(previous.target, self.target),
+            ..*self
+        }
+    }
+
+    /// The corners of the part of the view volume between `near` and `far`
+    /// units in front of the eye, in world space. The four near corners
+    /// come first.
+    pub fn frustum_corners(&self, near: f32, far: f32) -> [Point3<f32>; 8] {
+        use cgmath::SquareMatrix;
         let view = Matrix4::look_at_rh(self.eye, self.target, self.up);
-        let projection = cgmath::perspective(Deg(self.fov), self.ratio, self.znear, self.zfar);
-        return OPENGL_TO_WGPU_MATRIX * projection * view;
+        let projection = self.projection.build(&self.viewport, near, far);
+        let inverse = (projection * view).invert().unwrap_or_else(Matrix4::identity);
+        let mut corners = [Point3::new(0.0, 0.0, 0.0); 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            // cgmath projections map depth to -1..1, like OpenGL
+            let sign = |bit: usize| if i & bit == 0 { -1.0 } else { 1.0 };
+            *corner = Point3::from_homogeneous(inverse * Vector4::new(sign(1), sign(2), sign(4), 1.0));
+        }
+        corners
+    }
+
+    /// The view projection without the eye position, for things that are
+    /// infinitely far away like the sky. Orthographic cameras see them
+    /// through a 45° perspective, since all their view rays are parallel.
+    pub fn build_rotation_view_proj(&self) -> Matrix4<f32> {
+        let view = Matrix4::look_to_rh(Point3::new(0.0, 0.0, 0.0), self.target - self.eye, self.up);
+        let projection = match self.projection {
+            Projection::Perspective { .. } => self.projection,
+            Projection::Orthographic(_) => Projection::Perspective { fov: 45.0 },
+        };
+        OPENGL_TO_WGPU_MATRIX * projection.build(&self.viewport, self.znear, self.zfar) * view
+    }
+
+    fn build_view_proj(&self) -> cgmath::Matrix4<f32> {
+        let view = Matrix4::look_at_rh(self.eye, self.target, self.up);
+        let projection = self.projection.build(&self.viewport, self.znear, self.zfar);
+        OPENGL_TO_WGPU_MATRIX * projection * view
     }
 }
 
@@ -39,6 +161,15 @@ pub struct CameraUniform {
     // We can't use cgmath with bytemuck directly so we'll have
     // to convert the Matrix4 into a 4x4 f32 array
     view_proj: [[f32; 4]; 4],
+    /// Position of the eye, for specular lighting. `w` is unused, it only
+    /// pads the field to 16 bytes like WGSL expects.
+    view_pos: [f32; 4],
+}
+
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CameraUniform {
@@ -46,10 +177,29 @@ impl CameraUniform {
         use cgmath::SquareMatrix;
         Self {
             view_proj: cgmath::Matrix4::identity().into(),
+            view_pos: [0.0; 4],
         }
     }
 
-    pub fn update_view_proj(&mut self, camera: &Camera, window: &Window) {
-        self.view_proj = camera.build_view_proj(window).into();
+    pub fn update_view_proj(&mut self, camera: &Camera) {
+        self.view_proj = camera.build_view_proj().into();
+        self.view_pos = camera.eye.to_homogeneous().into();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_orthographic_sizes_stay_finite() {
+        let viewport = Viewport::new(800, 600, 1.0);
+        for size in [0.0, -1.0, f32::NAN] {
+            for size in [OrthographicSize::Height(size), OrthographicSize::PixelsPerUnit(size)] {
+                let projection = Projection::Orthographic(size).build(&viewport, 0.1, 100.0);
+                let columns: [[f32; 4]; 4] = projection.into();
+                assert!(columns.iter().flatten().all(|x| x.is_finite()), "{:?}", size);
+            }
+        }
     }
 }
@@ -0,0 +1,152 @@
+use winit::window::Window;
+
+use crate::{
+    error::{Error, Result},
+    instance::{Instance, InstanceRaw},
+    mesh::{Mesh, MeshIndex},
+    mipmap::Mipmapper,
+    texture,
+    vertex::Vertex,
+};
+
+/// The GPU objects every renderer needs, independent of where frames end up.
+pub struct Context {
+    pub instance: wgpu::Instance,
+    pub adapter: wgpu::Adapter,
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    /// Draws the mip chains of the textures uploaded with this context
+    pub mipmapper: Mipmapper,
+}
+
+impl Context {
+    /// Creates a context whose adapter can present to `window`, along with
+    /// the (not yet configured) surface for it.
+    ///
+    /// If no hardware adapter can present to the window, a fallback
+    /// (software) adapter is tried before giving up.
+    pub async fn new(window: &Window) -> Result<(Self, wgpu::Surface)> {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let surface = unsafe { instance.create_surface(&window) };
+        // Handle to the graphics card
+        let mut adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: Some(&surface),
+                force_fallback_adapter: false,
+            })
+            .await;
+        if adapter.is_none() {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    compatible_surface: Some(&surface),
+                    force_fallback_adapter: true,
+                })
+                .await;
+        }
+        let adapter = adapter.ok_or(Error::NoAdapter)?;
+        let (device, queue) = Self::request_device(&adapter).await?;
+        let mipmapper = Mipmapper::new(&device).await?;
+
+        let context = Self {
+            instance,
+            adapter,
+            device,
+            queue,
+            mipmapper,
+        };
+        Ok((context, surface))
+    }
+
+    /// Creates a context without a window.
+    ///
+    /// A fallback (software) adapter is preferred. If the platform doesn't
+    /// expose one, any available adapter is used instead.
+    pub async fn new_headless() -> Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let mut adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::LowPower,
+                compatible_surface: None,
+                force_fallback_adapter: true,
+            })
+            .await;
+        if adapter.is_none() {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::LowPower,
+                    compatible_surface: None,
+                    force_fallback_adapter: false,
+                })
+                .await;
+        }
+        let adapter = adapter.ok_or(Error::NoAdapter)?;
+        let (device, queue) = Self::request_device(&adapter).await?;
+        let mipmapper = Mipmapper::new(&device).await?;
+
+        Ok(Self {
+            instance,
+            adapter,
+            device,
+            queue,
+            mipmapper,
+        })
+    }
+
+    /// Requests a device with the default limits, or with the WebGL2 ones
+    /// if the adapter doesn't support those, like some fallback adapters.
+    async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue)> {
+        let request = |limits: wgpu::Limits| {
+            adapter.request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    features: wgpu::Features::empty(),
+                    limits: limits.using_resolution(adapter.limits()),
+                },
+                None,
+            )
+        };
+        match request(wgpu::Limits::default()).await {
+            Ok(device) => Ok(device),
+            Err(_) => Ok(request(wgpu::Limits::downlevel_webgl2_defaults()).await?),
+        }
+    }
+
+    /// Compiles WGSL source. Validation errors are returned instead of
+    /// going to the device's uncaptured error handler, which panics.
+    pub async fn create_shader_module(&self, label: &str, source: &str) -> Result<wgpu::ShaderModule> {
+        create_shader_module(&self.device, label, source).await
+    }
+
+    pub fn upload_mesh<I: MeshIndex>(&self, name: &str, vertices: &[Vertex], indices: &[I]) -> Mesh {
+        Mesh::new(&self.device, name, vertices, indices)
+    }
+
+    /// Decodes an encoded image (PNG or JPEG) and uploads it as a texture.
+    pub fn upload_texture(&self, bytes: &[u8], label: &str) -> Result<texture::Texture> {
+        texture::Texture::from_bytes(self, bytes, label)
+    }
+
+    pub fn upload_instances(&self, instances: &[Instance]) -> wgpu::Buffer {
+        InstanceRaw::create_buffer(&self.device, instances)
+    }
+}
+
+/// `Context::create_shader_module` for code that only has the device, such
+/// as the texture constructors.
+pub async fn create_shader_module(
+    device: &wgpu::Device,
+    label: &str,
+    source: &str,
+) -> Result<wgpu::ShaderModule> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+    match device.pop_error_scope().await {
+        Some(e) => Err(Error::ShaderCompile(e.to_string())),
+        None => Ok(module),
+    }
+}
@@ -0,0 +1,183 @@
+use std::time::Duration;
+
+use cgmath::{InnerSpace, Rad, Vector3};
+use winit::event::{
+    DeviceEvent, ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent,
+};
+
+use super::Controller;
+use crate::camera::Camera;
+
+/// Keeps the view from flipping over when looking straight up or down.
+const MAX_PITCH: Rad<f32> = Rad(std::f32::consts::FRAC_PI_2 - 0.01);
+
+/// First person controller: the mouse turns the view (yaw and pitch) and
+/// WASD/arrows walk along it, with Space/Shift moving up and down.
+///
+/// Clicking into the window grabs the cursor, Escape releases it. The view
+/// only turns while the cursor is grabbed.
+pub struct FpsController {
+    /// Units per second
+    speed: f32,
+    /// Radians turned per unit of mouse motion
+    sensitivity: f32,
+    /// Taken from the camera on the first update, so switching controllers
+    /// doesn't snap the view
+    orientation: Option<(Rad<f32>, Rad<f32>)>,
+    mouse_delta: (f64, f64),
+    cursor_grabbed: bool,
+    is_forward_pressed: bool,
+    is_backward_pressed: bool,
+    is_left_pressed: bool,
+    is_right_pressed: bool,
+    is_space_pressed: bool,
+    is_shift_pressed: bool,
+}
+
+impl FpsController {
+    pub fn new(speed: f32, sensitivity: f32) -> Self {
+        Self {
+            speed,
+            sensitivity,
+            orientation: None,
+            mouse_delta: (0.0, 0.0),
+            cursor_grabbed: false,
+            is_forward_pressed: false,
+            is_backward_pressed: false,
+            is_left_pressed: false,
+            is_right_pressed: false,
+            is_space_pressed: false,
+            is_shift_pressed: false,
+        }
+    }
+
+    fn orientation_of(camera: &Camera) -> (Rad<f32>, Rad<f32>) {
+        let forward = (camera.target - camera.eye).normalize();
+        let yaw = Rad(forward.z.atan2(forward.x));
+        let pitch = Rad(forward.y.asin());
+        (yaw, pitch)
+    }
+}
+
+impl Controller for FpsController {
+    fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.cursor_grabbed = true;
+                true
+            }
+            WindowEvent::Focused(false) => {
+                self.cursor_grabbed = false;
+                false
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(keycode),
+                        ..
+                    },
+                ..
+            } => {
+                let is_pressed = *state == ElementState::Pressed;
+                match keycode {
+                    VirtualKeyCode::Escape => {
+                        self.cursor_grabbed = false;
+                        true
+                    }
+                    VirtualKeyCode::W | VirtualKeyCode::Up => {
+                        self.is_forward_pressed = is_pressed;
+                        true
+                    }
+                    VirtualKeyCode::A | VirtualKeyCode::Left => {
+                        self.is_left_pressed = is_pressed;
+                        true
+                    }
+                    VirtualKeyCode::S | VirtualKeyCode::Down => {
+                        self.is_backward_pressed = is_pressed;
+                        true
+                    }
+                    VirtualKeyCode::D | VirtualKeyCode::Right => {
+                        self.is_right_pressed = is_pressed;
+                        true
+                    }
+                    VirtualKeyCode::Space => {
+                        self.is_space_pressed = is_pressed;
+                        true
+                    }
+                    VirtualKeyCode::LShift => {
+                        self.is_shift_pressed = is_pressed;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    fn process_device_events(&mut self, event: &DeviceEvent) -> bool {
+        match event {
+            DeviceEvent::MouseMotion { delta } if self.cursor_grabbed => {
+                self.mouse_delta.0 += delta.0;
+                self.mouse_delta.1 += delta.1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+        let (mut yaw, mut pitch) = self
+            .orientation
+            .unwrap_or_else(|| Self::orientation_of(camera));
+
+        let (dx, dy) = std::mem::take(&mut self.mouse_delta);
+        yaw += Rad(dx as f32 * self.sensitivity);
+        // Moving the mouse up gives a negative delta, which should look up
+        pitch -= Rad(dy as f32 * self.sensitivity);
+        pitch = Rad(pitch.0.clamp(-MAX_PITCH.0, MAX_PITCH.0));
+        self.orientation = Some((yaw, pitch));
+
+        let (sin_yaw, cos_yaw) = yaw.0.sin_cos();
+        let (sin_pitch, cos_pitch) = pitch.0.sin_cos();
+        let forward = Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw);
+        // Walk and strafe on the ground plane, no matter where we look
+        let walk = Vector3::new(cos_yaw, 0.0, sin_yaw);
+        let right = walk.cross(camera.up).normalize();
+
+        let mut movement = Vector3::new(0.0, 0.0, 0.0);
+        if self.is_forward_pressed {
+            movement += walk;
+        }
+        if self.is_backward_pressed {
+            movement -= walk;
+        }
+        if self.is_right_pressed {
+            movement += right;
+        }
+        if self.is_left_pressed {
+            movement -= right;
+        }
+        if self.is_space_pressed {
+            movement += camera.up;
+        }
+        if self.is_shift_pressed {
+            movement -= camera.up;
+        }
+        // Moving diagonally shouldn't be faster
+        if movement.magnitude2() > 0.0 {
+            camera.eye += movement.normalize() * self.speed * dt.as_secs_f32();
+        }
+
+        camera.target = camera.eye + forward;
+    }
+
+    fn wants_cursor_grab(&self) -> bool {
+        self.cursor_grabbed
+    }
+}
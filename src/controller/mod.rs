@@ -1,8 +1,59 @@
-use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+use std::time::Duration;
+
+use winit::{
+    event::{DeviceEvent, ElementState, KeyboardInput, VirtualKeyCode, WindowEvent},
+    window::{CursorGrabMode, Window},
+};
 
 use crate::camera::Camera;
 
+mod fps;
+mod orbit;
+
+pub use fps::FpsController;
+pub use orbit::OrbitController;
+
+/// Turns user input into camera movement.
+pub trait Controller {
+    /// Returns true if the event was used by the controller.
+    fn process_events(&mut self, event: &WindowEvent) -> bool;
+
+    /// Raw device input, e.g. mouse motion that keeps going when the cursor
+    /// hits the edge of the window.
+    fn process_device_events(&mut self, _event: &DeviceEvent) -> bool {
+        false
+    }
+
+    /// Moves the camera by however far it got in `dt`.
+    fn update_camera(&mut self, camera: &mut Camera, dt: Duration);
+
+    /// Whether the cursor should be grabbed and hidden right now.
+    fn wants_cursor_grab(&self) -> bool {
+        false
+    }
+}
+
+/// Locks the cursor to the window and hides it, or releases it again.
+pub fn set_cursor_grab(window: &Window, grab: bool) {
+    if grab {
+        // Not every platform supports locking, so fall back to confining
+        // the cursor to the window
+        let result = window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined));
+        if let Err(e) = result {
+            eprintln!("Could not grab the cursor: {}", e);
+        }
+    } else if let Err(e) = window.set_cursor_grab(CursorGrabMode::None) {
+        eprintln!("Could not release the cursor: {}", e);
+    }
+    window.set_cursor_visible(!grab);
+}
+
+/// Orbits the eye around the camera target with WASD/arrows and moves it
+/// up and down with Space/Shift.
 pub struct CameraController {
+    /// Units per second
     speed: f32,
     is_forward_pressed: bool,
     is_backward_pressed: bool,
@@ -25,8 +76,10 @@ impl CameraController {
 
         }
     }
+}
 
-    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+impl Controller for CameraController {
+    fn process_events(&mut self, event: &WindowEvent) -> bool {
         match event {
             WindowEvent::KeyboardInput {
                 input:
@@ -70,35 +123,37 @@ impl CameraController {
         }
     }
 
-    pub fn update_camera(&self, camera: &mut Camera) {
+    fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
         use cgmath::InnerSpace;
+        let step = self.speed * dt.as_secs_f32();
         let forward = camera.target - camera.eye;
         let forward_norm = forward.normalize();
         let forward_mag = forward.magnitude();
 
         let right = forward_norm.cross(camera.up);
 
-        if self.is_forward_pressed {
-            camera.eye += forward * self.speed;
+        // Stop before reaching the target, the view flips past it
+        if self.is_forward_pressed && forward_mag > step {
+            camera.eye += forward_norm * step;
         }
         if self.is_backward_pressed {
-            camera.eye -= forward * self.speed;
+            camera.eye -= forward_norm * step;
         }
+        // For small steps this moves the eye `step` units along the circle
+        // around the target
         if self.is_right_pressed {
-            camera.eye = camera.target - (forward + right * self.speed).normalize() * forward_mag;
+            camera.eye = camera.target - (forward + right * step).normalize() * forward_mag;
         }
         if self.is_left_pressed {
-            camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
+            camera.eye = camera.target - (forward - right * step).normalize() * forward_mag;
         }
 
-        let v_offset = 0.01;
-        
         if self.is_space_pressed {
-            camera.eye.y += v_offset;
+            camera.eye.y += step;
         }
 
         if self.is_shift_pressed {
-            camera.eye.y -= v_offset;
+            camera.eye.y -= step;
         }
     }
 }
@@ -0,0 +1,176 @@
+use std::time::Duration;
+
+use cgmath::{InnerSpace, Rad, Vector3};
+use winit::event::{DeviceEvent, ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+
+use super::Controller;
+use crate::camera::Camera;
+
+/// Keeps the eye from passing over the poles, where the view would flip.
+const MAX_PITCH: Rad<f32> = Rad(std::f32::consts::FRAC_PI_2 - 0.01);
+
+/// Orbits the eye around `Camera::target` on a sphere.
+///
+/// Dragging with the left mouse button rotates around the target, dragging
+/// with the middle button pans the target and the scroll wheel dollies in
+/// and out between `min_distance` and `max_distance`.
+pub struct OrbitController {
+    /// Radians turned per unit of mouse motion
+    pub rotate_speed: f32,
+    /// Fraction of the distance to the target panned per unit of mouse motion
+    pub pan_speed: f32,
+    /// Fraction of the distance to the target dollied per scroll line
+    pub zoom_speed: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// Yaw, pitch and distance around the target. Taken from the camera on
+    /// the first update, so switching controllers doesn't snap the view.
+    spherical: Option<(Rad<f32>, Rad<f32>, f32)>,
+    is_rotating: bool,
+    is_panning: bool,
+    mouse_delta: (f64, f64),
+    scroll: f32,
+}
+
+impl OrbitController {
+    pub fn new(min_distance: f32, max_distance: f32) -> Self {
+        Self {
+            rotate_speed: 0.005,
+            pan_speed: 0.002,
+            zoom_speed: 0.1,
+            min_distance,
+            max_distance,
+            spherical: None,
+            is_rotating: false,
+            is_panning: false,
+            mouse_delta: (0.0, 0.0),
+            scroll: 0.0,
+        }
+    }
+
+    /// An eye on the target has no direction, so it is moved to
+    /// `min_distance` along +X, or to 1 unit if that is 0.
+    fn spherical_of(&self, camera: &Camera) -> (Rad<f32>, Rad<f32>, f32) {
+        // Offset of the eye from the target
+        let offset = camera.eye - camera.target;
+        let distance = offset.magnitude();
+        if distance <= f32::EPSILON {
+            let distance = if self.min_distance > 0.0 { self.min_distance } else { 1.0 };
+            return (Rad(0.0), Rad(0.0), distance);
+        }
+        let yaw = Rad(offset.z.atan2(offset.x));
+        let pitch = (offset.y / distance).clamp(-1.0, 1.0).asin();
+        let pitch = Rad(pitch.clamp(-MAX_PITCH.0, MAX_PITCH.0));
+        (yaw, pitch, distance)
+    }
+}
+
+impl Controller for OrbitController {
+    fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::MouseInput { state, button, .. } => {
+                let is_pressed = *state == ElementState::Pressed;
+                match button {
+                    MouseButton::Left => {
+                        self.is_rotating = is_pressed;
+                        true
+                    }
+                    MouseButton::Middle => {
+                        self.is_panning = is_pressed;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    // Roughly one line per 20 pixels on touchpads
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+                };
+                true
+            }
+            WindowEvent::Focused(false) => {
+                self.is_rotating = false;
+                self.is_panning = false;
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn process_device_events(&mut self, event: &DeviceEvent) -> bool {
+        match event {
+            DeviceEvent::MouseMotion { delta } if self.is_rotating || self.is_panning => {
+                self.mouse_delta.0 += delta.0;
+                self.mouse_delta.1 += delta.1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Everything here is driven by input events, which already arrive at a
+    // rate independent of the frame rate
+    fn update_camera(&mut self, camera: &mut Camera, _dt: Duration) {
+        let (mut yaw, mut pitch, mut distance) = self
+            .spherical
+            .unwrap_or_else(|| self.spherical_of(camera));
+        let (dx, dy) = std::mem::take(&mut self.mouse_delta);
+        let (dx, dy) = (dx as f32, dy as f32);
+
+        if self.is_panning {
+            let forward = -offset_of(yaw, pitch);
+            let right = forward.cross(camera.up).normalize();
+            let up = right.cross(forward);
+            // Scale with the distance so the target follows the cursor at
+            // any zoom level
+            let pan = (up * dy - right * dx) * self.pan_speed * distance;
+            camera.target += pan;
+        } else if self.is_rotating {
+            yaw += Rad(dx * self.rotate_speed);
+            pitch += Rad(dy * self.rotate_speed);
+            pitch = Rad(pitch.0.clamp(-MAX_PITCH.0, MAX_PITCH.0));
+        }
+
+        let scroll = std::mem::take(&mut self.scroll);
+        // Dolly by a fraction of the distance, so zooming feels the same
+        // close to the target and far away from it
+        distance *= (1.0 - self.zoom_speed).powf(scroll);
+        distance = distance.clamp(self.min_distance, self.max_distance);
+        self.spherical = Some((yaw, pitch, distance));
+
+        camera.eye = camera.target + offset_of(yaw, pitch) * distance;
+    }
+}
+
+/// The direction from the target to the eye.
+fn offset_of(yaw: Rad<f32>, pitch: Rad<f32>) -> Vector3<f32> {
+    let (sin_yaw, cos_yaw) = yaw.0.sin_cos();
+    let (sin_pitch, cos_pitch) = pitch.0.sin_cos();
+    Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{point3, vec3};
+
+    use super::*;
+    use crate::camera::{Projection, Viewport};
+
+    #[test]
+    fn eyes_on_the_target_are_moved_away() {
+        let mut camera = Camera {
+            eye: point3(1.0, 2.0, 3.0),
+            up: vec3(0.0, 1.0, 0.0),
+            target: point3(1.0, 2.0, 3.0),
+            projection: Projection::Perspective { fov: 45.0 },
+            viewport: Viewport::new(800, 600, 1.0),
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        let mut controller = OrbitController::new(2.0, 10.0);
+        controller.update_camera(&mut camera, Duration::ZERO);
+        assert_eq!(camera.eye, point3(3.0, 2.0, 3.0));
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Everything that can go wrong while setting up or driving the renderer.
+#[derive(Debug)]
+pub enum Error {
+    /// No adapter matched the requested options, not even a fallback one.
+    NoAdapter,
+    /// The window couldn't be created.
+    Window(winit::error::OsError),
+    RequestDevice(wgpu::RequestDeviceError),
+    ImageDecode(image::ImageError),
+    /// An asset couldn't be read from disk.
+    Io(std::io::Error),
+    ObjLoad(tobj::LoadError),
+    Gltf(gltf::Error),
+    /// The asset folders couldn't be watched for changes.
+    Watch(notify::Error),
+    /// The sprites of an atlas don't fit in a texture of its maximum size.
+    AtlasFull { max_size: u32 },
+    /// A sprite added to an atlas has no texels.
+    EmptySprite { name: String },
+    /// The WGSL source failed validation. Holds the compiler's report.
+    ShaderCompile(String),
+    /// The surface has to be reconfigured before it can be drawn to again.
+    SurfaceLost,
+    /// Any other error returned while acquiring a surface texture.
+    Surface(wgpu::SurfaceError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoAdapter => write!(
+                f,
+                "no compatible graphics adapter found, make sure a Vulkan, Metal, DX12 or OpenGL driver is installed"
+            ),
+            Error::Window(e) => write!(f, "failed to create the window: {}", e),
+            Error::RequestDevice(e) => write!(f, "failed to open the graphics device: {}", e),
+            Error::ImageDecode(e) => write!(f, "failed to decode image: {}", e),
+            Error::Io(e) => write!(f, "failed to read asset: {}", e),
+            Error::ObjLoad(e) => write!(f, "failed to load OBJ model: {}", e),
+            Error::Gltf(e) => write!(f, "failed to import glTF: {}", e),
+            Error::Watch(e) => write!(f, "failed to watch assets: {}", e),
+            Error::AtlasFull { max_size } => {
+                write!(f, "the sprites don't fit in a {}x{} atlas", max_size, max_size)
+            }
+            Error::EmptySprite { name } => write!(f, "the sprite {:?} is empty", name),
+            Error::ShaderCompile(report) => write!(f, "failed to compile shader: {}", report),
+            Error::SurfaceLost => write!(f, "the window surface was lost"),
+            Error::Surface(e) => write!(f, "failed to acquire the next frame: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Window(e) => Some(e),
+            Error::RequestDevice(e) => Some(e),
+            Error::ImageDecode(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::ObjLoad(e) => Some(e),
+            Error::Gltf(e) => Some(e),
+            Error::Watch(e) => Some(e),
+            Error::Surface(e) => Some(e),
+            Error::NoAdapter
+            | Error::AtlasFull { .. }
+            | Error::EmptySprite { .. }
+            | Error::ShaderCompile(_)
+            | Error::SurfaceLost => None,
+        }
+    }
+}
+
+impl From<winit::error::OsError> for Error {
+    fn from(e: winit::error::OsError) -> Self {
+        Error::Window(e)
+    }
+}
+
+impl From<wgpu::RequestDeviceError> for Error {
+    fn from(e: wgpu::RequestDeviceError) -> Self {
+        Error::RequestDevice(e)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(e: image::ImageError) -> Self {
+        Error::ImageDecode(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<tobj::LoadError> for Error {
+    fn from(e: tobj::LoadError) -> Self {
+        Error::ObjLoad(e)
+    }
+}
+
+impl From<gltf::Error> for Error {
+    fn from(e: gltf::Error) -> Self {
+        Error::Gltf(e)
+    }
+}
+
+impl From<notify::Error> for Error {
+    fn from(e: notify::Error) -> Self {
+        Error::Watch(e)
+    }
+}
+
+impl From<wgpu::SurfaceError> for Error {
+    fn from(e: wgpu::SurfaceError) -> Self {
+        match e {
+            wgpu::SurfaceError::Lost => Error::SurfaceLost,
+            e => Error::Surface(e),
+        }
+    }
+}
@@ -0,0 +1,735 @@
+//! Golden image tests for the render pipeline.
+//!
+//! Every test renders a fixed scene on a headless state and compares it with
+//! a reference PNG checked in under `tests/golden`. When a comparison fails,
+//! the actual frame and a diff image are written to `target/golden` so the
+//! change can be inspected.
+//!
+//! Run the tests with `UPDATE_GOLDEN=1` to (re)write the reference images
+//! after an intended change to what the pipeline draws.
+
+use std::path::PathBuf;
+
+use anyhow::*;
+use cgmath::{point3, vec3, Rotation3};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    assets::Assets,
+    atlas::AtlasBuilder,
+    camera::{Camera, OrthographicSize, Projection, Viewport},
+    instance::{Instance, InstanceRaw},
+    light::{Attenuation, Light},
+    model::{Material, Model},
+    pbr::{PbrFactors, PbrMaterial, PbrTextures},
+    scene::SceneObject,
+    shapes,
+    state::State,
+    texture::{Mipmaps, Texture, TextureOptions},
+    vertex::{TextureLoad, DIRT_LAYER, TREE_LAYER},
+};
+
+const SCENE_SIZE: PhysicalSize<u32> = PhysicalSize::new(128, 128);
+
+/// How far a rendered frame may drift from its reference image.
+#[derive(Debug, Clone, Copy)]
+pub struct Tolerance {
+    /// Largest difference allowed in any channel of a single pixel.
+    pub per_channel: u8,
+    /// Number of pixels that may exceed `per_channel` before the
+    /// comparison fails.
+    pub max_failing_pixels: usize,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        // Software rasterizers don't agree exactly on edges and filtering
+        Self {
+            per_channel: 2,
+            max_failing_pixels: 0,
+        }
+    }
+}
+
+pub struct Comparison {
+    pub passed: bool,
+    pub failing_pixels: usize,
+    pub max_difference: u8,
+    /// The expected image dimmed to grey, with failing pixels in red.
+    pub diff: image::RgbaImage,
+}
+
+pub fn compare(
+    actual: &image::RgbaImage,
+    expected: &image::RgbaImage,
+    tolerance: Tolerance,
+) -> Result<Comparison> {
+    ensure!(
+        actual.dimensions() == expected.dimensions(),
+        "Frame is {:?} but the reference image is {:?}",
+        actual.dimensions(),
+        expected.dimensions()
+    );
+
+    let mut failing_pixels = 0;
+    let mut max_difference = 0;
+    let mut diff = image::RgbaImage::new(expected.width(), expected.height());
+    for ((a, e), d) in actual.pixels().zip(expected.pixels()).zip(diff.pixels_mut()) {
+        let difference = a.0.iter().zip(e.0.iter()).map(|(a, e)| a.abs_diff(*e)).max().unwrap();
+        max_difference = max_difference.max(difference);
+        *d = if difference > tolerance.per_channel {
+            failing_pixels += 1;
+            image::Rgba([255, 0, 0, 255])
+        } else {
+            let luma = (e[0] as u32 * 3 + e[1] as u32 * 6 + e[2] as u32) / 10;
+            let dimmed = (luma / 3) as u8;
+            image::Rgba([dimmed, dimmed, dimmed, 255])
+        };
+    }
+
+    Ok(Comparison {
+        passed: failing_pixels <= tolerance.max_failing_pixels,
+        failing_pixels,
+        max_difference,
+        diff,
+    })
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.png", name))
+}
+
+fn output_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/golden")
+}
+
+/// Compares `actual` with the reference image called `name`.
+pub fn check_golden(name: &str, actual: &image::RgbaImage, tolerance: Tolerance) -> Result<()> {
+    let reference = reference_path(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(reference.parent().unwrap())?;
+        actual.save(&reference)?;
+        return Ok(());
+    }
+
+    let expected = image::open(&reference)
+        .with_context(|| format!("Missing reference image {:?}", reference))?
+        .to_rgba8();
+    let comparison = compare(actual, &expected, tolerance)?;
+    if comparison.passed {
+        return Ok(());
+    }
+
+    let output = output_dir();
+    std::fs::create_dir_all(&output)?;
+    let actual_path = output.join(format!("{}-actual.png", name));
+    let diff_path = output.join(format!("{}-diff.png", name));
+    actual.save(&actual_path)?;
+    comparison.diff.save(&diff_path)?;
+    bail!(
+        "{}: {} pixels differ by more than {} (max difference {}). See {:?} and {:?}",
+        name,
+        comparison.failing_pixels,
+        tolerance.per_channel,
+        comparison.max_difference,
+        actual_path,
+        diff_path
+    )
+}
+
+/// A fixed scene that doesn't depend on input or timing.
+pub struct Scene {
+    pub camera: Camera,
+    pub instances: Vec<Instance>,
+    pub texture: TextureLoad,
+}
+
+pub fn load(scene: Scene) -> Result<State> {
+    let mut state = pollster::block_on(State::new_headless(SCENE_SIZE))?;
+    state.set_camera(scene.camera);
+    state.set_instances(scene.instances);
+    state.set_texture(scene.texture);
+    Ok(state)
+}
+
+pub fn render(scene: Scene) -> Result<image::RgbaImage> {
+    let mut state = load(scene)?;
+    state.render()?;
+    state.read_frame()
+}
+
+fn camera(eye: cgmath::Point3<f32>, target: cgmath::Point3<f32>) -> Camera {
+    Camera {
+        eye,
+        up: vec3(0.0, 1.0, 0.0),
+        target,
+        projection: Projection::Perspective { fov: 45.0 },
+        viewport: Viewport::new(SCENE_SIZE.width, SCENE_SIZE.height, 1.0),
+        znear: 0.1,
+        zfar: 100.0,
+    }
+}
+
+fn quad(x: f32, y: f32, z: f32, angle: f32) -> Instance {
+    Instance {
+        pos: vec3(x, y, z),
+        rot: cgmath::Quaternion::from_axis_angle(vec3(0.0, 1.0, 0.0), cgmath::Deg(angle)),
+        scale: vec3(1.0, 1.0, 1.0),
+        texture_layer: TREE_LAYER,
+    }
+}
+
+/// `quad` with the dirt texture.
+fn dirt_quad(x: f32, y: f32, z: f32, angle: f32) -> Instance {
+    Instance {
+        texture_layer: DIRT_LAYER,
+        ..quad(x, y, z, angle)
+    }
+}
+
+#[test]
+fn single_quad() {
+    let frame = render(Scene {
+        camera: camera(point3(0.0, 0.0, 3.0), point3(0.0, 0.0, 0.0)),
+        instances: vec![quad(0.0, 0.0, 0.0, 0.0)],
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    check_golden("single_quad", &frame, Tolerance::default()).unwrap();
+}
+
+#[test]
+fn rotated_quads() {
+    let frame = render(Scene {
+        camera: camera(point3(0.0, 1.0, 4.0), point3(0.0, 0.0, 0.0)),
+        instances: vec![dirt_quad(-1.0, 0.0, 0.0, 30.0), dirt_quad(1.0, 0.0, -1.0, -30.0)],
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    check_golden("rotated_quads", &frame, Tolerance::default()).unwrap();
+}
+
+#[test]
+fn instance_grid() {
+    let instances = (0..10)
+        .flat_map(|z| (0..10).map(move |x| quad(x as f32 - 4.5, 0.0, z as f32 - 4.5, 45.0)))
+        .collect();
+    let frame = render(Scene {
+        camera: camera(point3(0.0, 8.0, 10.0), point3(0.0, 0.0, 0.0)),
+        instances,
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    check_golden("instance_grid", &frame, Tolerance::default()).unwrap();
+}
+
+#[test]
+fn texture_layers() {
+    // Tree and dirt instances in a checkerboard, drawn in one call
+    let instances = (0..3)
+        .flat_map(|y| {
+            (0..3).map(move |x| match (x + y) % 2 {
+                0 => quad(x as f32 * 1.7 - 1.7, y as f32 * 1.7 - 1.7, 0.0, 0.0),
+                _ => dirt_quad(x as f32 * 1.7 - 1.7, y as f32 * 1.7 - 1.7, 0.0, 0.0),
+            })
+        })
+        .collect();
+    let frame = render(Scene {
+        camera: camera(point3(0.0, 0.0, 7.0), point3(0.0, 0.0, 0.0)),
+        instances,
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    check_golden("texture_layers", &frame, Tolerance::default()).unwrap();
+}
+
+#[test]
+fn depth_sorting() {
+    // The closer quad is drawn first, so without a depth buffer the one
+    // behind it would be painted over it
+    let frame = render(Scene {
+        camera: camera(point3(0.0, 0.0, 4.0), point3(0.0, 0.0, 0.0)),
+        instances: vec![quad(0.4, 0.0, 1.0, 0.0), quad(-0.4, 0.0, 0.0, 0.0)],
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    check_golden("depth_sorting", &frame, Tolerance::default()).unwrap();
+}
+
+#[test]
+fn resize_keeps_aspect_ratio() {
+    // A square should stay square when the target gets wider
+    let mut state = load(Scene {
+        camera: camera(point3(0.0, 0.0, 3.0), point3(0.0, 0.0, 0.0)),
+        instances: vec![quad(0.0, 0.0, 0.0, 0.0)],
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    state.resize(PhysicalSize::new(SCENE_SIZE.width * 2, SCENE_SIZE.height));
+    state.render().unwrap();
+    let frame = state.read_frame().unwrap();
+    check_golden("resize_keeps_aspect_ratio", &frame, Tolerance::default()).unwrap();
+}
+
+#[test]
+fn orthographic_pixels_per_unit() {
+    let mut scene_camera = camera(point3(0.0, 0.0, 3.0), point3(0.0, 0.0, 0.0));
+    scene_camera.projection = Projection::Orthographic(OrthographicSize::PixelsPerUnit(32.0));
+    let frame = render(Scene {
+        camera: scene_camera,
+        instances: vec![dirt_quad(-1.0, 0.0, 0.0, 0.0), dirt_quad(1.0, 0.0, -2.0, 0.0)],
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    check_golden("orthographic_pixels_per_unit", &frame, Tolerance::default()).unwrap();
+}
+
+#[test]
+fn obj_model() {
+    // Two sub-meshes with their own materials, indexed with u32
+    let mut state = load(Scene {
+        camera: camera(point3(1.5, 1.5, 2.5), point3(0.0, 0.0, 0.0)),
+        instances: vec![quad(0.0, 0.0, 0.0, 0.0), quad(-1.5, 0.0, -1.5, 30.0)],
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    state
+        .load_model(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets/cube.obj"))
+        .unwrap();
+    state.render().unwrap();
+    let frame = state.read_frame().unwrap();
+    check_golden("obj_model", &frame, Tolerance::default()).unwrap();
+}
+
+#[test]
+fn gltf_scene() {
+    // The .gltf file embeds its buffer as a data URI and refers to an
+    // external image, the .glb file embeds both. They describe the same scene
+    for file in ["assets/quads.gltf", "assets/quads.glb"] {
+        let mut state = load(Scene {
+            camera: camera(point3(0.0, 1.5, 3.0), point3(0.0, 0.0, 0.0)),
+            instances: Vec::new(),
+            texture: TextureLoad::Array,
+        })
+        .unwrap();
+        state
+            .load_scene(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(file))
+            .unwrap();
+        state.render().unwrap();
+        let frame = state.read_frame().unwrap();
+        check_golden("gltf_scene", &frame, Tolerance::default()).unwrap();
+    }
+}
+
+#[test]
+fn mirrored_and_double_sided_gltf_nodes() {
+    // The scene of gltf_scene seen from below, with the left quad mirrored
+    // and the floor double sided. Culling would hide both
+    let assets = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let gltf = std::fs::read_to_string(assets.join("quads.gltf"))
+        .unwrap()
+        .replace(r#""name": "Left","#, r#""name": "Left", "scale": [-1, 1, 1],"#)
+        .replace(r#""name": "Blue","#, r#""name": "Blue", "doubleSided": true,"#);
+    let folder = std::env::temp_dir().join(format!("learning_wgpu-mirrored-{}", std::process::id()));
+    std::fs::create_dir_all(&folder).unwrap();
+    std::fs::copy(assets.join("dirt.png"), folder.join("dirt.png")).unwrap();
+    std::fs::write(folder.join("quads.gltf"), gltf).unwrap();
+
+    let mut state = load(Scene {
+        camera: camera(point3(0.0, -1.5, 3.0), point3(0.0, 0.0, 0.0)),
+        instances: Vec::new(),
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    let loaded = state.load_scene(folder.join("quads.gltf"));
+    std::fs::remove_dir_all(&folder).unwrap();
+    loaded.unwrap();
+    state.render().unwrap();
+    let frame = state.read_frame().unwrap();
+    check_golden("mirrored_gltf_nodes", &frame, Tolerance::default()).unwrap();
+}
+
+#[test]
+fn lights() {
+    // A white sphere for each kind of light: lit from above by the sun, from
+    // the front by a red point light and from above the camera by a blue spot
+    // light
+    let mut state = load(Scene {
+        camera: camera(point3(0.0, 0.0, 4.0), point3(0.0, 0.0, 0.0)),
+        instances: vec![quad(-1.1, 0.0, 0.0, 0.0), quad(0.0, 0.0, 0.0, 0.0), quad(1.1, 0.0, 0.0, 0.0)],
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    let context = state.context();
+    let white = Texture::from_color(context, [255; 4], "white").unwrap();
+    let sphere = shapes::uv_sphere(0.5, 32, 16).upload(&context.device, "sphere");
+    let white = state.add_texture(white);
+    state.set_texture(white);
+    state.set_mesh(sphere);
+
+    state.set_ambient_light([0.05; 3]);
+    state
+        .add_light(Light::Directional {
+            direction: vec3(0.0, -1.0, 0.0),
+            color: [1.0, 1.0, 0.9],
+            intensity: 0.6,
+        })
+        .unwrap();
+    let point = state
+        .add_light(Light::Point {
+            position: point3(0.0, 0.0, 0.0),
+            color: [1.0, 0.2, 0.2],
+            intensity: 1.0,
+            attenuation: Attenuation::range(10.0),
+        })
+        .unwrap();
+    state
+        .add_light(Light::Spot {
+            position: point3(1.1, 2.0, 2.0),
+            direction: vec3(0.0, -1.0, -1.0),
+            color: [0.3, 0.5, 1.0],
+            intensity: 2.0,
+            attenuation: Attenuation::NONE,
+            inner_angle: cgmath::Deg(8.0),
+            outer_angle: cgmath::Deg(12.0),
+        })
+        .unwrap();
+    // Lights can be moved after they were added
+    let mut moved = *state.light(point).unwrap();
+    moved.set_position(point3(-1.1, 0.0, 1.5));
+    assert!(state.set_light(point, moved));
+
+    state.render().unwrap();
+    let frame = state.read_frame().unwrap();
+    check_golden("lights", &frame, Tolerance::default()).unwrap();
+}
+
+#[test]
+fn pbr_materials() {
+    // From left to right: rough plastic, smooth plastic, polished gold and a
+    // glowing sphere, all lit by the same sun
+    let mut state = load(Scene {
+        camera: camera(point3(0.0, 0.0, 5.0), point3(0.0, 0.0, 0.0)),
+        instances: vec![quad(0.0, 0.0, 0.0, 0.0)],
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    let spheres = [
+        (-1.5, [0.8, 0.1, 0.1, 1.0], 0.0, 0.8, [0.0; 3]),
+        (-0.5, [0.8, 0.1, 0.1, 1.0], 0.0, 0.2, [0.0; 3]),
+        (0.5, [1.0, 0.77, 0.34, 1.0], 1.0, 0.3, [0.0; 3]),
+        (1.5, [0.1, 0.1, 0.1, 1.0], 0.0, 0.5, [0.2, 0.6, 1.0]),
+    ];
+    let context = state.context();
+    let mut model = Model {
+        meshes: Vec::new(),
+        materials: Vec::new(),
+    };
+    for (i, (x, base_color, metallic, roughness, emissive)) in spheres.into_iter().enumerate() {
+        let mut sphere = shapes::uv_sphere(0.45, 32, 16);
+        for vertex in &mut sphere.vertices {
+            vertex.position[0] += x;
+        }
+        let factors = PbrFactors {
+            base_color,
+            metallic,
+            roughness,
+            emissive,
+            ..Default::default()
+        };
+        let pbr = PbrMaterial::new(context, PbrTextures::default(), factors, "sphere").unwrap();
+        let white = Texture::from_color(context, [255; 4], "white").unwrap();
+        let material = Material::new_pbr(&context.device, state.pbr_material_layout(), "sphere", white, pbr);
+        model.meshes.push(sphere.upload(&context.device, "sphere").with_material(i));
+        model.materials.push(material);
+    }
+    state.set_model(model);
+
+    state.set_ambient_light([0.03; 3]);
+    state
+        .add_light(Light::Directional {
+            direction: vec3(-1.0, -1.0, -1.0),
+            color: [1.0; 3],
+            intensity: 4.0,
+        })
+        .unwrap();
+
+    state.render().unwrap();
+    let frame = state.read_frame().unwrap();
+    check_golden("pbr_materials", &frame, Tolerance::default()).unwrap();
+}
+
+#[test]
+fn shadows() {
+    // A 10x10 grid of cubes on the ground, lit by a low sun. The cascades
+    // follow the camera, so the near cubes get sharper shadows than the far
+    // ones
+    let mut state = load(Scene {
+        camera: camera(point3(0.0, 8.0, 10.0), point3(0.0, 0.0, 0.0)),
+        instances: Vec::new(),
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    let context = state.context();
+    let white = Texture::from_color(context, [255; 4], "white").unwrap();
+    let ground = shapes::plane(12.0, 12.0, 1, 1).upload(&context.device, "ground");
+    let cube = shapes::cube(0.5).upload(&context.device, "cube");
+    let cubes = (0..10)
+        .flat_map(|z| (0..10).map(move |x| quad(x as f32 - 4.5, 0.25, z as f32 - 4.5, 30.0)))
+        .collect::<Vec<_>>();
+    let ground_instances = vec![quad(0.0, 0.0, 0.0, 0.0)];
+    let objects = vec![
+        SceneObject {
+            name: "ground".to_string(),
+            meshes: 0..1,
+            instance_buffer: InstanceRaw::create_buffer(&context.device, &ground_instances),
+            instances: ground_instances.iter().map(Instance::to_raw).collect(),
+            mirrored: false,
+        },
+        SceneObject {
+            name: "cubes".to_string(),
+            meshes: 1..2,
+            instance_buffer: InstanceRaw::create_buffer(&context.device, &cubes),
+            instances: cubes.iter().map(Instance::to_raw).collect(),
+            mirrored: false,
+        },
+    ];
+    let scene = crate::scene::Scene {
+        model: Model {
+            meshes: vec![ground, cube],
+            materials: Vec::new(),
+        },
+        objects,
+    };
+    let white = state.add_texture(white);
+    state.set_texture(white);
+    state.set_scene(scene);
+
+    state.set_ambient_light([0.2; 3]);
+    state
+        .add_light(Light::Directional {
+            direction: vec3(-1.0, -1.0, -0.6),
+            color: [1.0; 3],
+            intensity: 0.8,
+        })
+        .unwrap();
+
+    state.render().unwrap();
+    let frame = state.read_frame().unwrap();
+    check_golden("shadows", &frame, Tolerance::default()).unwrap();
+}
+
+#[test]
+fn mipmaps() {
+    // A fine checkerboard on a floor that reaches far into the distance,
+    // which fades to grey instead of breaking up into moire patterns
+    let mut state = load(Scene {
+        camera: camera(point3(0.0, 1.0, 4.0), point3(0.0, 0.0, -10.0)),
+        instances: Vec::new(),
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    let context = state.context();
+    let checker = image::RgbaImage::from_fn(256, 256, |x, y| {
+        if (x / 8 + y / 8) % 2 == 0 {
+            image::Rgba([255; 4])
+        } else {
+            image::Rgba([0, 0, 0, 255])
+        }
+    });
+    let checker = Texture::from_image_with_options(
+        context,
+        &image::DynamicImage::ImageRgba8(checker),
+        Some("checker"),
+        &TextureOptions::trilinear(),
+    )
+    .unwrap();
+    let floor = shapes::plane(40.0, 40.0, 1, 1).upload(&context.device, "floor");
+    let instances = vec![quad(0.0, 0.0, -10.0, 0.0)];
+    let scene = crate::scene::Scene {
+        model: Model {
+            meshes: vec![floor],
+            materials: Vec::new(),
+        },
+        objects: vec![SceneObject {
+            name: "floor".to_string(),
+            meshes: 0..1,
+            instance_buffer: InstanceRaw::create_buffer(&context.device, &instances),
+            instances: instances.iter().map(Instance::to_raw).collect(),
+            mirrored: false,
+        }],
+    };
+    let checker = state.add_texture(checker);
+    state.set_texture(checker);
+    state.set_scene(scene);
+
+    state.render().unwrap();
+    let frame = state.read_frame().unwrap();
+    check_golden("mipmaps", &frame, Tolerance::default()).unwrap();
+}
+
+#[test]
+fn pixel_art_magnification() {
+    // Close enough to the tree that every texel covers several pixels,
+    // which stay sharp squares with TextureOptions::pixel_art
+    let frame = render(Scene {
+        camera: camera(point3(-0.1, 0.1, 0.15), point3(-0.1, 0.1, 0.0)),
+        instances: vec![quad(0.0, 0.0, 0.0, 0.0)],
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    check_golden("pixel_art_magnification", &frame, Tolerance::default()).unwrap();
+}
+
+#[test]
+fn atlas_sprites() {
+    // The tree and dirt packed into one atlas, each on its own quad but
+    // drawn with the same texture
+    let mut state = load(Scene {
+        camera: camera(point3(0.0, 0.0, 4.0), point3(0.0, 0.0, 0.0)),
+        instances: Vec::new(),
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    let context = state.context();
+    let mut builder = AtlasBuilder::new();
+    builder.add("tree", &image::load_from_memory(include_bytes!("../assets/tree.png")).unwrap());
+    builder.add("dirt", &image::load_from_memory(include_bytes!("../assets/dirt.png")).unwrap());
+    let options = TextureOptions {
+        mipmaps: Mipmaps::None,
+        ..TextureOptions::trilinear()
+    };
+    let atlas = builder.build(context, Some("atlas"), &options).unwrap();
+
+    let mut meshes = Vec::new();
+    let mut objects = Vec::new();
+    for (i, name) in ["tree", "dirt"].into_iter().enumerate() {
+        let mut sprite = shapes::plane(1.6, 1.6, 1, 1);
+        atlas.uv_rect(name).unwrap().apply(&mut sprite);
+        meshes.push(sprite.upload(&context.device, name));
+        // Stand the plane up, facing the camera
+        let instances = vec![Instance {
+            rot: cgmath::Quaternion::from_axis_angle(vec3(1.0, 0.0, 0.0), cgmath::Deg(90.0)),
+            ..quad(i as f32 * 1.8 - 0.9, 0.0, 0.0, 0.0)
+        }];
+        objects.push(SceneObject {
+            name: name.to_string(),
+            meshes: i..i + 1,
+            instance_buffer: InstanceRaw::create_buffer(&context.device, &instances),
+            instances: instances.iter().map(Instance::to_raw).collect(),
+            mirrored: false,
+        });
+    }
+    let scene = crate::scene::Scene {
+        model: Model {
+            meshes,
+            materials: Vec::new(),
+        },
+        objects,
+    };
+    let atlas = state.add_texture(atlas.texture);
+    state.set_texture(atlas);
+    state.set_scene(scene);
+
+    state.render().unwrap();
+    let frame = state.read_frame().unwrap();
+    check_golden("atlas_sprites", &frame, Tolerance::default()).unwrap();
+}
+
+#[test]
+fn skybox() {
+    // A quad in front of the corner where the -X, +Y and -Z faces of the
+    // sky meet. Each face has a white texel in its top left corner
+    let mut state = load(Scene {
+        camera: camera(point3(2.0, -2.0, 2.5), point3(0.0, 0.0, 0.0)),
+        instances: vec![quad(0.0, 0.0, 0.0, 0.0)],
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    let colors = [
+        [200, 60, 60, 255],
+        [60, 200, 200, 255],
+        [60, 200, 60, 255],
+        [200, 60, 200, 255],
+        [60, 60, 200, 255],
+        [200, 200, 60, 255],
+    ];
+    let faces = colors.map(|color| {
+        let mut face = image::RgbaImage::from_pixel(4, 4, image::Rgba(color));
+        face.put_pixel(0, 0, image::Rgba([255; 4]));
+        image::DynamicImage::ImageRgba8(face)
+    });
+    let context = state.context();
+    let sky = Texture::from_cube_faces(&context.device, &context.queue, &faces, Some("sky")).unwrap();
+    state.set_skybox(sky);
+
+    state.render().unwrap();
+    let frame = state.read_frame().unwrap();
+    check_golden("skybox", &frame, Tolerance::default()).unwrap();
+}
+
+#[test]
+fn released_textures_cannot_be_selected() {
+    let mut state = load(Scene {
+        camera: camera(point3(0.0, 0.0, 3.0), point3(0.0, 0.0, 0.0)),
+        instances: vec![quad(0.0, 0.0, 0.0, 0.0)],
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    let context = state.context();
+    let white = Texture::from_color(context, [255; 4], "white").unwrap();
+    let white = state.add_texture(white);
+    state.release_texture(white);
+    assert!(!state.set_texture(white));
+    state.render().unwrap();
+}
+
+#[test]
+fn textures_of_other_assets_are_rejected() {
+    let mut state = load(Scene {
+        camera: camera(point3(0.0, 0.0, 3.0), point3(0.0, 0.0, 0.0)),
+        instances: vec![quad(0.0, 0.0, 0.0, 0.0)],
+        texture: TextureLoad::Array,
+    })
+    .unwrap();
+    let context = state.context();
+    let white = Texture::from_color(context, [255; 4], "white").unwrap();
+    // The first handle of a new `Assets`, which used to match the array
+    let mut other = Assets::new();
+    let white = other.add(white);
+
+    assert!(!state.set_texture(TextureLoad::Custom(white)));
+    // Neither releases the array nor the other texture
+    state.release_texture(TextureLoad::Custom(white));
+    state.render().unwrap();
+    assert_eq!(other.ref_count(white), 1);
+}
+
+#[test]
+fn compare_reports_pixels_outside_tolerance() {
+    let expected = image::RgbaImage::from_pixel(4, 4, image::Rgba([100, 100, 100, 255]));
+    let mut actual = expected.clone();
+    actual.put_pixel(0, 0, image::Rgba([102, 100, 100, 255]));
+    actual.put_pixel(1, 0, image::Rgba([110, 100, 100, 255]));
+
+    let comparison = compare(&actual, &expected, Tolerance::default()).unwrap();
+    assert_eq!(comparison.failing_pixels, 1);
+    assert_eq!(comparison.max_difference, 10);
+    assert_eq!(*comparison.diff.get_pixel(1, 0), image::Rgba([255, 0, 0, 255]));
+    assert!(!comparison.passed);
+
+    let looser = Tolerance {
+        per_channel: 2,
+        max_failing_pixels: 1,
+    };
+    assert!(compare(&actual, &expected, looser).unwrap().passed);
+}
+
+#[test]
+fn compare_rejects_mismatched_sizes() {
+    let a = image::RgbaImage::new(4, 4);
+    let b = image::RgbaImage::new(4, 2);
+    assert!(compare(&a, &b, Tolerance::default()).is_err());
+}
@@ -0,0 +1,108 @@
+use anyhow::*;
+use winit::dpi::PhysicalSize;
+
+/// Format of the offscreen colour target. It matches `image::RgbaImage`
+/// byte for byte, so read back frames can be saved without conversion.
+pub const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// A colour target that is not backed by a window surface.
+///
+/// Frames rendered into `view` are copied into `output_buffer` at the end
+/// of the frame so they can be mapped and read back on the CPU.
+pub struct OffscreenTarget {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    output_buffer: wgpu::Buffer,
+    size: PhysicalSize<u32>,
+    // Rows in a buffer copy have to be aligned to COPY_BYTES_PER_ROW_ALIGNMENT
+    padded_bytes_per_row: u32,
+}
+
+impl OffscreenTarget {
+    pub fn new(device: &wgpu::Device, size: PhysicalSize<u32>) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Texture"),
+            size: wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: OFFSCREEN_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let unpadded_bytes_per_row = 4 * size.width;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+        let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen Output Buffer"),
+            size: (padded_bytes_per_row * size.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            texture,
+            view,
+            output_buffer,
+            size,
+            padded_bytes_per_row,
+        }
+    }
+
+    /// Records a copy of the colour target into the output buffer.
+    pub fn copy_to_buffer(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.output_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(self.padded_bytes_per_row),
+                    rows_per_image: std::num::NonZeroU32::new(self.size.height),
+                },
+            },
+            wgpu::Extent3d {
+                width: self.size.width,
+                height: self.size.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// Maps the output buffer and returns the last copied frame.
+    ///
+    /// This blocks until the GPU has finished all submitted work.
+    pub fn read(&self, device: &wgpu::Device) -> Result<image::RgbaImage> {
+        let slice = self.output_buffer.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            // The receiver only goes away if `read` already returned
+            let _ = tx.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        rx.recv()??;
+
+        let unpadded_bytes_per_row = (4 * self.size.width) as usize;
+        let mut pixels = Vec::with_capacity(unpadded_bytes_per_row * self.size.height as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(self.padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row]);
+            }
+        }
+        self.output_buffer.unmap();
+
+        image::RgbaImage::from_raw(self.size.width, self.size.height, pixels)
+            .context("Offscreen frame does not match the target size")
+    }
+}
@@ -1,69 +1,70 @@
+use wgpu::util::DeviceExt;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Instance {
     /// Position
     pub pos: cgmath::Vector3<f32>,
     /// Rotation
     pub rot: cgmath::Quaternion<f32>,
+    /// Scale along each axis, applied before the rotation
+    pub scale: cgmath::Vector3<f32>,
+    /// The layer of the texture array the instance is drawn with, e.g.
+    /// `vertex::TREE_LAYER`. Has to be a layer of the array. Only used with
+    /// `TextureLoad::Array`
+    pub texture_layer: u32,
 }
 
 impl Instance {
     pub fn to_raw(&self) -> InstanceRaw {
         let translation = cgmath::Matrix4::from_translation(self.pos);
         let rotation = cgmath::Matrix4::from(self.rot);
+        let scale = cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z);
         InstanceRaw {
-            model: (translation * rotation).into()
+            model: (translation * rotation * scale).into(),
+            texture_layer: self.texture_layer,
         }
     }
    
 }
 
-// NEW!
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct InstanceRaw {
     pub model: [[f32; 4]; 4],
+    pub texture_layer: u32,
 }
 
 impl InstanceRaw {
-    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        use std::mem;
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
-            // We need to switch from using a step mode of Vertex to Instance
-            // This means that our shaders will only change to use the next
-            // instance when the shader starts processing a new instance
-            step_mode: wgpu::VertexStepMode::Instance,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    // While our vertex shader only uses locations 0, and 1 now, in later tutorials we'll
-                    // be using 2, 3, and 4, for Vertex. We'll start at slot 5 not conflict with them later
-                    shader_location: 5,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                // A mat4 takes up 4 vertex slots as it is technically 4 vec4s. We need to define a slot
-                // for each vec4. We'll have to reassemble the mat4 in
-                // the shader.
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
-                    shader_location: 6,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
-                    shader_location: 7,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
-                    shader_location: 8,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-            ],
+    /// An instance at any transform, including skewed ones an `Instance`
+    /// can't represent. Drawn with the first layer of the texture array.
+    pub fn from_transform(transform: cgmath::Matrix4<f32>) -> Self {
+        Self {
+            model: transform.into(),
+            texture_layer: 0,
         }
     }
-}
 
- 
+    pub fn create_buffer(device: &wgpu::Device, instances: &[Instance]) -> wgpu::Buffer {
+        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        Self::upload(device, &instance_data)
+    }
+
+    pub fn upload(device: &wgpu::Device, instance_data: &[InstanceRaw]) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Instance Buffer"),
+            contents: bytemuck::cast_slice(instance_data),
+            usage: wgpu::BufferUsages::VERTEX,
+        })
+    }
+}
 
- 
\ No newline at end of file
+// A mat4 takes up 4 vertex slots as it is technically 4 vec4s. We need to
+// define a slot for each column and reassemble the mat4 in the shader.
+// Locations 0 to 4 are left for the vertex attributes.
+crate::vertex_layout!(InstanceRaw, Instance {
+    model[0] => 5,
+    model[1] => 6,
+    model[2] => 7,
+    model[3] => 8,
+    texture_layer => 9,
+});
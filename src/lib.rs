@@ -0,0 +1,41 @@
+//! A small instanced renderer built on wgpu.
+//!
+//! [`Context`] owns the device and queue and uploads meshes, textures and
+//! instances. [`State`] ties them together with a camera into a render
+//! pipeline that draws either into a window or, for headless use, into an
+//! offscreen texture.
+
+pub mod assets;
+pub mod atlas;
+pub mod camera;
+pub mod context;
+pub mod controller;
+pub mod error;
+#[cfg(test)]
+mod golden;
+pub mod headless;
+pub mod instance;
+pub mod light;
+pub mod mesh;
+pub mod mipmap;
+pub mod model;
+pub mod pbr;
+pub mod scene;
+pub mod shadow;
+pub mod shapes;
+pub mod skybox;
+pub mod state;
+pub mod texture;
+pub mod time;
+pub mod vertex;
+
+pub use camera::Camera;
+pub use context::Context;
+pub use error::{Error, Result};
+pub use instance::Instance;
+pub use light::Light;
+pub use mesh::Mesh;
+pub use model::Model;
+pub use scene::Scene;
+pub use state::State;
+pub use texture::Texture;
@@ -0,0 +1,367 @@
+use cgmath::{Deg, InnerSpace, Point3, Vector3};
+use wgpu::util::DeviceExt;
+
+/// How many lights fit in the light uniform. Uniform buffers are used
+/// instead of storage buffers, which WebGL doesn't have, so the size is
+/// fixed.
+pub const MAX_LIGHTS: usize = 16;
+
+/// How the intensity of a point or spot light falls off with the distance
+/// `d`: `1 / (constant + linear * d + quadratic * d * d)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Attenuation {
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+impl Attenuation {
+    /// No falloff at all.
+    pub const NONE: Self = Self {
+        constant: 1.0,
+        linear: 0.0,
+        quadratic: 0.0,
+    };
+
+    /// A falloff that fades to about 1% at `range` units from the light.
+    pub fn range(range: f32) -> Self {
+        Self {
+            constant: 1.0,
+            linear: 4.5 / range,
+            quadratic: 75.0 / (range * range),
+        }
+    }
+}
+
+impl Default for Attenuation {
+    fn default() -> Self {
+        Self::range(50.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Light {
+    /// Light coming from infinitely far away, like the sun. The first one
+    /// in `Lights::iter` casts shadows.
+    Directional {
+        /// The direction the light travels in
+        direction: Vector3<f32>,
+        color: [f32; 3],
+        intensity: f32,
+    },
+    /// Light shining from a point in every direction, like a light bulb.
+    Point {
+        position: Point3<f32>,
+        color: [f32; 3],
+        intensity: f32,
+        attenuation: Attenuation,
+    },
+    /// Light shining from a point in a cone, like a flashlight. It is at
+    /// full intensity within `inner_angle` of `direction` and fades out
+    /// towards `outer_angle`.
+    Spot {
+        position: Point3<f32>,
+        direction: Vector3<f32>,
+        color: [f32; 3],
+        intensity: f32,
+        attenuation: Attenuation,
+        inner_angle: Deg<f32>,
+        outer_angle: Deg<f32>,
+    },
+}
+
+impl Light {
+    /// Moves a point or spot light. Directional lights have no position.
+    pub fn set_position(&mut self, new_position: Point3<f32>) {
+        match self {
+            Light::Point { position, .. } | Light::Spot { position, .. } => *position = new_position,
+            Light::Directional { .. } => {}
+        }
+    }
+
+    /// Turns a directional or spot light. Point lights have no direction.
+    pub fn set_direction(&mut self, new_direction: Vector3<f32>) {
+        match self {
+            Light::Directional { direction, .. } | Light::Spot { direction, .. } => {
+                *direction = new_direction
+            }
+            Light::Point { .. } => {}
+        }
+    }
+
+    fn to_raw(self) -> LightRaw {
+        let direction = |direction: &Vector3<f32>| {
+            let direction = direction.normalize();
+            [direction.x, direction.y, direction.z]
+        };
+        let attenuation = |a: &Attenuation| [a.constant, a.linear, a.quadratic];
+        match &self {
+            Light::Directional {
+                direction: d,
+                color,
+                intensity,
+            } => LightRaw::new(LightRaw::DIRECTIONAL, [0.0; 3], direction(d), *color, *intensity),
+            Light::Point {
+                position,
+                color,
+                intensity,
+                attenuation: a,
+            } => LightRaw {
+                attenuation: extend(attenuation(a), -1.0),
+                ..LightRaw::new(LightRaw::POINT, (*position).into(), [0.0; 3], *color, *intensity)
+            },
+            Light::Spot {
+                position,
+                direction: d,
+                color,
+                intensity,
+                attenuation: a,
+                inner_angle,
+                outer_angle,
+            } => {
+                let cos_inner = cgmath::Angle::cos(*inner_angle);
+                let cos_outer = cgmath::Angle::cos(*outer_angle);
+                LightRaw {
+                    direction: extend(direction(d), cos_inner),
+                    attenuation: extend(attenuation(a), cos_outer),
+                    ..LightRaw::new(LightRaw::SPOT, (*position).into(), [0.0; 3], *color, *intensity)
+                }
+            }
+        }
+    }
+}
+
+fn extend(v: [f32; 3], w: f32) -> [f32; 4] {
+    [v[0], v[1], v[2], w]
+}
+
+/// A light as the shader sees it. Matches `Light` in `shader.wgsl`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightRaw {
+    /// `w` is the kind of light
+    position: [f32; 4],
+    /// `w` is the cosine of a spot light's inner angle
+    direction: [f32; 4],
+    /// `w` is the intensity
+    color: [f32; 4],
+    /// Constant, linear and quadratic attenuation. `w` is the cosine of a
+    /// spot light's outer angle
+    attenuation: [f32; 4],
+}
+
+impl LightRaw {
+    const DIRECTIONAL: f32 = 0.0;
+    const POINT: f32 = 1.0;
+    const SPOT: f32 = 2.0;
+
+    fn new(kind: f32, position: [f32; 3], direction: [f32; 3], color: [f32; 3], intensity: f32) -> Self {
+        Self {
+            position: extend(position, kind),
+            direction: extend(direction, 0.0),
+            color: extend(color, intensity),
+            attenuation: extend([1.0, 0.0, 0.0], -1.0),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightsUniform {
+    /// Light that reaches every surface. `w` is unused
+    ambient: [f32; 4],
+    count: u32,
+    _padding: [u32; 3],
+    lights: [LightRaw; MAX_LIGHTS],
+}
+
+/// Identifies a light added with `Lights::add`. Once the light is removed
+/// the id no longer finds anything, even after its slot is reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LightId {
+    index: usize,
+    generation: u32,
+}
+
+#[derive(Default)]
+struct Slot {
+    /// Counts the removals, so ids of removed lights don't match the next
+    /// light in the slot
+    generation: u32,
+    light: Option<Light>,
+}
+
+impl Slot {
+    fn get(&self, id: LightId) -> Option<&Light> {
+        self.light.as_ref().filter(|_| self.generation == id.generation)
+    }
+}
+
+/// The lights of a scene and the uniform buffer they are uploaded to.
+///
+/// While there are no lights, surfaces are drawn unlit with their plain
+/// texture colors.
+pub struct Lights {
+    /// Removed lights leave a hole, so the ids of the others stay valid
+    slots: Vec<Slot>,
+    ambient: [f32; 3],
+    buffer: wgpu::Buffer,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Lights {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Buffer"),
+            contents: bytemuck::bytes_of(&LightsUniform::zeroed()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("light_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("light_bind_group"),
+        });
+        Self {
+            slots: Vec::new(),
+            ambient: [0.1; 3],
+            buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    /// Adds a light, or returns `None` if there are `MAX_LIGHTS` already.
+    pub fn add(&mut self, queue: &wgpu::Queue, light: Light) -> Option<LightId> {
+        let index = match self.slots.iter().position(|slot| slot.light.is_none()) {
+            Some(free) => free,
+            None if self.slots.len() < MAX_LIGHTS => {
+                self.slots.push(Slot::default());
+                self.slots.len() - 1
+            }
+            None => return None,
+        };
+        let slot = &mut self.slots[index];
+        slot.light = Some(light);
+        let id = LightId {
+            index,
+            generation: slot.generation,
+        };
+        self.upload(queue);
+        Some(id)
+    }
+
+    /// Replaces a light, e.g. to move it. Returns false if it was removed.
+    pub fn set(&mut self, queue: &wgpu::Queue, id: LightId, light: Light) -> bool {
+        if self.get(id).is_none() {
+            return false;
+        }
+        self.slots[id.index].light = Some(light);
+        self.upload(queue);
+        true
+    }
+
+    pub fn remove(&mut self, queue: &wgpu::Queue, id: LightId) -> Option<Light> {
+        self.get(id)?;
+        let slot = &mut self.slots[id.index];
+        slot.generation += 1;
+        let light = slot.light.take();
+        self.upload(queue);
+        light
+    }
+
+    pub fn get(&self, id: LightId) -> Option<&Light> {
+        self.slots.get(id.index)?.get(id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (LightId, &Light)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let id = LightId {
+                index,
+                generation: slot.generation,
+            };
+            Some((id, slot.light.as_ref()?))
+        })
+    }
+
+    /// The light that casts shadows: the first directional light, as its
+    /// index in the light uniform and the direction it shines in.
+    pub fn shadow_caster(&self) -> Option<(u32, Vector3<f32>)> {
+        self.iter()
+            .take(MAX_LIGHTS)
+            .enumerate()
+            .find_map(|(index, (_, light))| match light {
+                Light::Directional { direction, .. } => Some((index as u32, *direction)),
+                _ => None,
+            })
+    }
+
+    /// Sets the light that reaches every surface, even facing away from all
+    /// lights.
+    pub fn set_ambient(&mut self, queue: &wgpu::Queue, ambient: [f32; 3]) {
+        self.ambient = ambient;
+        self.upload(queue);
+    }
+
+    fn upload(&self, queue: &wgpu::Queue) {
+        let mut uniform = LightsUniform {
+            ambient: extend(self.ambient, 0.0),
+            ..LightsUniform::zeroed()
+        };
+        for (raw, (_, light)) in uniform.lights.iter_mut().zip(self.iter()) {
+            *raw = light.to_raw();
+            uniform.count += 1;
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+}
+
+impl LightsUniform {
+    fn zeroed() -> Self {
+        bytemuck::Zeroable::zeroed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sun(intensity: f32) -> Light {
+        Light::Directional {
+            direction: cgmath::vec3(0.0, -1.0, 0.0),
+            color: [1.0; 3],
+            intensity,
+        }
+    }
+
+    #[test]
+    fn removed_ids_dont_find_the_next_light_in_their_slot() {
+        let context = pollster::block_on(crate::Context::new_headless()).unwrap();
+        let mut lights = Lights::new(&context.device);
+        let removed = lights.add(&context.queue, sun(1.0)).unwrap();
+        assert!(lights.remove(&context.queue, removed).is_some());
+        let added = lights.add(&context.queue, sun(2.0)).unwrap();
+
+        assert_ne!(added, removed);
+        assert!(lights.get(removed).is_none());
+        assert!(!lights.set(&context.queue, removed, sun(3.0)));
+        assert!(lights.remove(&context.queue, removed).is_none());
+        assert_eq!(lights.get(added), Some(&sun(2.0)));
+    }
+}
@@ -1,67 +1,188 @@
+use cgmath::prelude::*;
+use learning_wgpu::{
+    controller::{self, CameraController, Controller, FpsController, OrbitController},
+    time::{Clock, FixedTimestep, FrameTime},
+    Error, Instance, State,
+};
 use winit::{
     dpi::PhysicalSize,
-    event::{Event, WindowEvent},
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
 
-mod camera;
-mod controller;
-mod state;
-mod texture;
-mod vertex;
-mod instance;
 const MIN_WINDOW_SIZE: PhysicalSize<i32> = PhysicalSize::new(400, 400);
+const HEADLESS_SIZE: PhysicalSize<u32> = PhysicalSize::new(400, 400);
+/// Simulation updates per second
+const UPDATE_RATE: f64 = 60.0;
 
 fn main() {
-    pollster::block_on(run());
+    // Warnings of the renderer, e.g. about assets that failed to load, are
+    // shown by default. Set RUST_LOG for more
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("learning_wgpu=warn")).init();
+    // `--headless <output.png>` renders a single frame without opening a window
+    // and `--model <file>` draws an OBJ model instead of the square, or a
+    // glTF scene (.gltf or .glb) instead of the grid
+    let args = std::env::args().collect::<Vec<_>>();
+    let model = args
+        .iter()
+        .position(|arg| arg == "--model")
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str);
+    match args.iter().position(|arg| arg == "--headless") {
+        Some(i) => {
+            let output = args.get(i + 1).map_or("frame.png", String::as_str);
+            if let Err(e) = pollster::block_on(run_headless(output, model)) {
+                eprintln!("{:?}", e);
+                std::process::exit(1);
+            }
+        }
+        None => {
+            if let Err(e) = pollster::block_on(run(model)) {
+                eprintln!("Could not start the renderer: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// A 10x10 grid of squares, each rotated away from the origin, with tree
+/// and dirt textures in a checkerboard pattern.
+fn instance_grid() -> Vec<Instance> {
+    const INSTANCES_PER_ROW: u32 = 10;
+    const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(INSTANCES_PER_ROW as f32 * 0.7, 0.0, INSTANCES_PER_ROW as f32 * 0.5);
+    (0..INSTANCES_PER_ROW).flat_map(|z| {
+        (0..INSTANCES_PER_ROW).map(move |x| {
+            let pos = cgmath::Vector3 { x: x as f32, y: 0.0, z: z as f32 } - INSTANCE_DISPLACEMENT;
+
+            let rot = if pos.is_zero() {
+                // this is needed so an object at (0, 0, 0) won't get scaled to zero
+                // as Quaternions can effect scale if they're not created correctly
+                cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(0.0))
+            } else {
+                cgmath::Quaternion::from_axis_angle(pos.normalize(), cgmath::Deg(45.0))
+            };
+
+            Instance {
+                pos,
+                rot,
+                scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+                texture_layer: (x + z) % 2,
+            }
+        })
+    }).collect()
+}
+
+fn is_gltf(path: &str) -> bool {
+    let extension = std::path::Path::new(path).extension();
+    extension.is_some_and(|extension| extension == "gltf" || extension == "glb")
+}
+
+/// Loads what `--model` points at. glTF scenes place their own instances,
+/// anything else is drawn on the grid.
+fn load_model(state: &mut State, model: Option<&str>) -> Result<(), Error> {
+    match model {
+        Some(scene) if is_gltf(scene) => state.load_scene(scene)?,
+        Some(model) => {
+            state.load_model(model)?;
+            state.set_instances(instance_grid());
+        }
+        None => state.set_instances(instance_grid()),
+    }
+    Ok(())
+}
+
+async fn run_headless(output: &str, model: Option<&str>) -> anyhow::Result<()> {
+    let mut state = State::new_headless(HEADLESS_SIZE).await?;
+    load_model(&mut state, model)?;
+    state.update(FrameTime::default());
+    state.render()?;
+    state.save_frame(output)?;
+    println!("Saved frame to {}", output);
+    Ok(())
 }
 
-async fn run() {
+async fn run(model: Option<&str>) -> Result<(), Error> {
     let event_loop = EventLoop::new();
 
     let window = WindowBuilder::new()
         .with_title("Learning WGPU")
         .with_min_inner_size(MIN_WINDOW_SIZE)
         .with_inner_size(MIN_WINDOW_SIZE)
-        .build(&event_loop)
-        .unwrap();
+        .build(&event_loop)?;
 
-    let mut state = state::State::new(&window).await;
+    let mut state = State::new(&window).await?;
+    load_model(&mut state, model)?;
+    // Edited textures, models and scenes show up without a restart
+    if let Err(e) = state.watch_assets() {
+        eprintln!("{}", e);
+    }
+    // C cycles through the keyboard, first person and orbit controllers
+    let mut controller_index = 0;
+    let mut cursor_grabbed = false;
+    let mut clock = Clock::new();
+    let mut timestep = FixedTimestep::new(UPDATE_RATE);
     event_loop.run(move |event, _, flow| {
         match event {
-            Event::RedrawRequested(id) => {
-                if id == window.id() {
-                    state.update(&window);
-                    match state.render() {
-                        Ok(_) => {}
-                        // Reconfigure the surface if lost
-                        Err(wgpu::SurfaceError::Lost) => state.resize(state.get_size()),
-                        // The system is out of memory, we should probably quit
-                        Err(wgpu::SurfaceError::OutOfMemory) => *flow = ControlFlow::Exit,
-                        // All other errors (Outdated, Timeout) should be resolved by the next frame
-                        Err(e) => eprintln!("{:?}", e),
+            Event::RedrawRequested(id) if id == window.id() => {
+                timestep.accumulate(clock.tick().delta);
+                while let Some(time) = timestep.next_step() {
+                    state.update(time);
+                }
+                match state.render_interpolated(timestep.alpha()) {
+                    Ok(_) => {}
+                    // Reconfigure the surface if lost
+                    Err(Error::SurfaceLost) => state.resize(state.get_size()),
+                    // The system is out of memory, we should probably quit
+                    Err(e @ Error::Surface(wgpu::SurfaceError::OutOfMemory)) => {
+                        eprintln!("{}", e);
+                        *flow = ControlFlow::Exit
                     }
+                    // All other errors (Outdated, Timeout) should be resolved by the next frame
+                    Err(e) => eprintln!("{}", e),
                 }
             }
             Event::WindowEvent {
                 window_id,
                 ref event,
-            } => {
-                if window_id == window.id() && !state.input(event) {
-                    match event {
-                        WindowEvent::CloseRequested => *flow = ControlFlow::Exit,
-                        WindowEvent::Resized(size) => {
-                            state.resize(*size);
-                        }
-                        WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                            state.resize(**new_inner_size);
-                        }
-                        _ => (),
-                    }
+            } if window_id == window.id() && !state.input(event) => match event {
+                WindowEvent::CloseRequested => *flow = ControlFlow::Exit,
+                WindowEvent::Resized(size) => {
+                    state.resize(*size);
+                }
+                WindowEvent::ScaleFactorChanged {
+                    scale_factor,
+                    new_inner_size,
+                } => {
+                    state.rescale(*scale_factor, **new_inner_size);
                 }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::C),
+                            ..
+                        },
+                    ..
+                } => {
+                    controller_index = (controller_index + 1) % 3;
+                    let controller: Box<dyn Controller> = match controller_index {
+                        0 => Box::new(CameraController::new(2.0)),
+                        1 => Box::new(FpsController::new(3.0, 0.003)),
+                        _ => Box::new(OrbitController::new(1.0, 50.0)),
+                    };
+                    state.set_controller(controller);
+                }
+                _ => (),
+            },
+            Event::DeviceEvent { ref event, .. } => {
+                state.device_input(event);
             }
             Event::MainEventsCleared => {
+                if state.wants_cursor_grab() != cursor_grabbed {
+                    cursor_grabbed = !cursor_grabbed;
+                    controller::set_cursor_grab(&window, cursor_grabbed);
+                }
                 // RedrawRequested will only trigger once, unless we manually
                 // request it.
                 window.request_redraw();
@@ -0,0 +1,117 @@
+use cgmath::{InnerSpace, Vector2, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::vertex::Vertex;
+
+/// Integer types that can be used as indices into a vertex buffer.
+pub trait MeshIndex: bytemuck::Pod {
+    const FORMAT: wgpu::IndexFormat;
+}
+
+impl MeshIndex for u16 {
+    const FORMAT: wgpu::IndexFormat = wgpu::IndexFormat::Uint16;
+}
+
+impl MeshIndex for u32 {
+    const FORMAT: wgpu::IndexFormat = wgpu::IndexFormat::Uint32;
+}
+
+/// Geometry uploaded to the GPU, ready to be drawn with instancing.
+pub struct Mesh {
+    pub name: String,
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub index_format: wgpu::IndexFormat,
+    pub num_indices: u32,
+    /// Index into the materials of the `Model` this mesh belongs to, if any
+    pub material: Option<usize>,
+}
+
+impl Mesh {
+    pub fn new<I: MeshIndex>(
+        device: &wgpu::Device,
+        name: &str,
+        vertices: &[Vertex],
+        indices: &[I],
+    ) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Index Buffer", name)),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Self {
+            name: name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            index_format: I::FORMAT,
+            num_indices: indices.len() as u32,
+            material: None,
+        }
+    }
+
+    pub fn with_material(mut self, material: usize) -> Self {
+        self.material = Some(material);
+        self
+    }
+}
+
+/// Sets the normal of every vertex to the average of the triangles around
+/// it, weighted by their area. For meshes that come without normals.
+pub fn generate_normals(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); vertices.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| Vector3::from(vertices[triangle[i] as usize].position));
+        // Twice the area of the triangle, facing the front side
+        let normal = (b - a).cross(c - a);
+        for &i in triangle {
+            normals[i as usize] += normal;
+        }
+    }
+    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+        vertex.normal = if normal.magnitude2() > 0.0 { normal.normalize().into() } else { [0.0, 0.0, 1.0] };
+    }
+}
+
+/// Fills in the tangents from the triangles' positions, UVs and the vertex
+/// normals, which have to be set already.
+pub fn generate_tangents(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut tangents = vec![Vector3::new(0.0, 0.0, 0.0); vertices.len()];
+    let mut bitangents = tangents.clone();
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| vertices[triangle[i] as usize]);
+        let edge1 = Vector3::from(b.position) - Vector3::from(a.position);
+        let edge2 = Vector3::from(c.position) - Vector3::from(a.position);
+        let duv1 = Vector2::from(b.uv) - Vector2::from(a.uv);
+        let duv2 = Vector2::from(c.uv) - Vector2::from(a.uv);
+        let determinant = duv1.x * duv2.y - duv2.x * duv1.y;
+        if determinant.abs() < f32::EPSILON {
+            continue;
+        }
+        let tangent = (edge1 * duv2.y - edge2 * duv1.y) / determinant;
+        let bitangent = (edge2 * duv1.x - edge1 * duv2.x) / determinant;
+        for &i in triangle {
+            tangents[i as usize] += tangent;
+            bitangents[i as usize] += bitangent;
+        }
+    }
+
+    for ((vertex, tangent), bitangent) in vertices.iter_mut().zip(tangents).zip(bitangents) {
+        let normal = Vector3::from(vertex.normal);
+        // Make the tangent perpendicular to the normal (Gram-Schmidt)
+        let mut tangent = tangent - normal * normal.dot(tangent);
+        if tangent.magnitude2() < 1e-12 {
+            // No usable UVs, any tangent will do
+            let axis = if normal.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+            tangent = normal.cross(axis).cross(normal);
+        }
+        let tangent = tangent.normalize();
+        let w = if normal.cross(tangent).dot(bitangent) < 0.0 { -1.0 } else { 1.0 };
+        vertex.tangent = [tangent.x, tangent.y, tangent.z, w];
+    }
+}
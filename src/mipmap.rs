@@ -0,0 +1,241 @@
+//! Mip chains for textures uploaded from images.
+//!
+//! Devices may be created with WebGL2 limits, which have no compute
+//! shaders, so every level is drawn from the one above it with a render
+//! pass instead.
+
+use std::{collections::HashMap, num::NonZeroU32};
+
+use crate::{context, error::Result, texture::TextureOptions};
+
+/// The number of levels of a full mip chain, down to 1x1.
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    u32::BITS - width.max(height).max(1).leading_zeros()
+}
+
+/// Whether `format` can be a render target on every device, which
+/// `Mipmapper` needs.
+pub fn is_renderable(format: wgpu::TextureFormat) -> bool {
+    format
+        .describe()
+        .guaranteed_format_features
+        .allowed_usages
+        .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+}
+
+/// Draws the levels below the first one of textures, each from the one
+/// above it with linear filtering. Created once per device with its
+/// `Context`, which passes it to the texture constructors.
+///
+/// The GL backend can't sample one level of a texture while drawing into
+/// another, so every level is also drawn into a scratch texture that the
+/// next one is drawn from.
+///
+/// sRGB textures are averaged in linear space.
+pub struct Mipmapper {
+    /// One for every format `TextureOptions::format` can return that
+    /// `is_renderable`
+    pipelines: HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>,
+    sampler: wgpu::Sampler,
+}
+
+impl Mipmapper {
+    pub async fn new(device: &wgpu::Device) -> Result<Self> {
+        let shader = context::create_shader_module(device, "Mipmap Shader", include_str!("mipmap.wgsl")).await?;
+        let pipelines = [true, false]
+            .map(|srgb| TextureOptions { srgb, ..Default::default() }.format())
+            .into_iter()
+            .filter(|&format| is_renderable(format))
+            .map(|format| (format, Self::create_pipeline(device, &shader, format)))
+            .collect();
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Mipmap Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Ok(Self { pipelines, sampler })
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Mipmap Pipeline"),
+            // Derived from the shader
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vertex_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fragment_main",
+                targets: &[Some(format.into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    /// Whether `generate` can draw the levels of textures in `format`.
+    pub fn supports(&self, format: wgpu::TextureFormat) -> bool {
+        self.pipelines.contains_key(&format)
+    }
+
+    /// Fills the levels below the first one of every array layer of
+    /// `texture`. It needs `COPY_SRC` and `RENDER_ATTACHMENT` usages.
+    ///
+    /// Panics if the format isn't one the mipmapper `supports`.
+    pub fn generate(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        format: wgpu::TextureFormat,
+        size: wgpu::Extent3d,
+        mip_level_count: u32,
+    ) {
+        if mip_level_count < 2 {
+            return;
+        }
+        let pipeline = &self.pipelines[&format];
+        let bind_group_layout = pipeline.get_bind_group_layout(0);
+
+        let level_size = |level: u32| wgpu::Extent3d {
+            width: (size.width >> level).max(1),
+            height: (size.height >> level).max(1),
+            depth_or_array_layers: 1,
+        };
+        let scratch = |level: u32| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Mipmap Scratch Texture"),
+                size: level_size(level),
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::COPY_DST,
+            })
+        };
+        fn level_copy(texture: &wgpu::Texture, level: u32, layer: u32) -> wgpu::ImageCopyTexture<'_> {
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: level,
+                origin: wgpu::Origin3d { x: 0, y: 0, z: layer },
+                aspect: wgpu::TextureAspect::All,
+            }
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mipmap Encoder"),
+        });
+        for layer in 0..size.depth_or_array_layers {
+            let mut above = scratch(0);
+            encoder.copy_texture_to_texture(level_copy(texture, 0, layer), level_copy(&above, 0, 0), level_size(0));
+            for level in 1..mip_level_count {
+                let below = scratch(level);
+                let source = above.create_view(&wgpu::TextureViewDescriptor::default());
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&source),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                    ],
+                    label: Some("mipmap_bind_group"),
+                });
+
+                // Once into the level itself and once into the scratch
+                // texture the next level is drawn from
+                let level_view = texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Mipmap Level"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_mip_level: level,
+                    mip_level_count: NonZeroU32::new(1),
+                    base_array_layer: layer,
+                    array_layer_count: NonZeroU32::new(1),
+                    ..Default::default()
+                });
+                let scratch_view = below.create_view(&wgpu::TextureViewDescriptor::default());
+                for target in [&level_view, &scratch_view] {
+                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: Some("Mipmap Pass"),
+                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                            view: target,
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                                store: true,
+                            },
+                        })],
+                        depth_stencil_attachment: None,
+                    });
+                    render_pass.set_pipeline(pipeline);
+                    render_pass.set_bind_group(0, &bind_group, &[]);
+                    render_pass.draw(0..3, 0..1);
+                }
+                above = below;
+            }
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+}
+
+/// The levels below `img` of a mip chain with `mip_level_count` levels,
+/// each downscaled from the one above it with a triangle filter.
+///
+/// This is the fallback for formats the `Mipmapper` can't render to.
+/// Unlike the GPU path it averages sRGB images without decoding them, so
+/// the smaller levels come out slightly darker.
+pub fn downscale(img: &image::RgbaImage, mip_level_count: u32) -> Vec<image::RgbaImage> {
+    let mut levels: Vec<image::RgbaImage> = Vec::new();
+    for _ in 1..mip_level_count {
+        let above = levels.last().unwrap_or(img);
+        let (width, height) = above.dimensions();
+        let level = image::imageops::resize(
+            above,
+            (width / 2).max(1),
+            (height / 2).max(1),
+            image::imageops::FilterType::Triangle,
+        );
+        levels.push(level);
+    }
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chains_end_at_one_texel() {
+        assert_eq!(mip_level_count(1, 1), 1);
+        assert_eq!(mip_level_count(256, 256), 9);
+        assert_eq!(mip_level_count(300, 20), 9);
+
+        let img = image::RgbaImage::new(300, 20);
+        let sizes = downscale(&img, mip_level_count(300, 20))
+            .iter()
+            .map(|level| level.dimensions())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, [(150, 10), (75, 5), (37, 2), (18, 1), (9, 1), (4, 1), (2, 1), (1, 1)]);
+    }
+}
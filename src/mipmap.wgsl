@@ -0,0 +1,27 @@
+// Draws a mip level from the one above it, see mipmap.rs
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+
+struct VOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vertex_main(@builtin(vertex_index) index: u32) -> VOutput {
+    // A single triangle covering the whole level
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VOutput;
+    out.uv = uv;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fragment_main(input: VOutput) -> @location(0) vec4<f32> {
+    // Halfway between four source texels, so linear filtering averages them
+    return textureSample(source, source_sampler, input.uv);
+}
@@ -0,0 +1,239 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    context::Context,
+    error::Result,
+    mesh::{self, Mesh},
+    pbr::PbrMaterial,
+    texture::Texture,
+    vertex::Vertex,
+};
+
+/// The surface of a mesh.
+///
+/// Materials with `pbr` set are drawn with the metallic-roughness shader,
+/// the others with the Blinn-Phong shader, which only uses the diffuse
+/// texture.
+pub struct Material {
+    pub name: String,
+    /// Also the base color texture of PBR materials
+    pub diffuse_texture: Texture,
+    /// `diffuse_texture` bound to the texture bind group layout, or
+    /// everything bound to the PBR material layout if `pbr` is set
+    pub bind_group: wgpu::BindGroup,
+    pub pbr: Option<PbrMaterial>,
+}
+
+impl Material {
+    /// A material for the Blinn-Phong shader. `layout` is the texture bind
+    /// group layout.
+    pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, name: &str, diffuse_texture: Texture) -> Self {
+        let bind_group = diffuse_texture.create_bind_group(device, layout, name);
+        Self {
+            name: name.to_string(),
+            diffuse_texture,
+            bind_group,
+            pbr: None,
+        }
+    }
+
+    /// A material for the PBR shader. `layout` is the one created by
+    /// `PbrMaterial::bind_group_layout`.
+    pub fn new_pbr(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        name: &str,
+        base_color_texture: Texture,
+        pbr: PbrMaterial,
+    ) -> Self {
+        let bind_group = pbr.create_bind_group(device, layout, &base_color_texture, name);
+        Self {
+            name: name.to_string(),
+            diffuse_texture: base_color_texture,
+            bind_group,
+            pbr: Some(pbr),
+        }
+    }
+}
+
+/// A set of meshes loaded from a single file, along with their materials.
+/// Each mesh refers to its material by index, see `Mesh::material`.
+pub struct Model {
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<Material>,
+}
+
+impl Model {
+    /// Loads a Wavefront OBJ file, along with the MTL files it references.
+    ///
+    /// Every object or group in the file becomes one mesh. Faces are
+    /// triangulated and indexed with `u32`, so meshes can have any number
+    /// of vertices. Normals are generated if the file has none, tangents
+    /// are always generated. Diffuse textures are looked up relative to the
+    /// OBJ file. Materials without one get a 1x1 texture of their diffuse
+    /// color and meshes without a material are drawn with the current
+    /// texture, as are all meshes if the MTL files can't be loaded.
+    pub fn load_obj<P: AsRef<Path>>(
+        context: &Context,
+        layout: &wgpu::BindGroupLayout,
+        path: P,
+    ) -> Result<Self> {
+        let device = &context.device;
+        let path = path.as_ref();
+        let (models, obj_materials) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)?;
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+        // The geometry is still usable without its materials
+        let obj_materials = obj_materials.unwrap_or_else(|e| {
+            log::warn!("Could not load the materials of {}: {}", path.display(), e);
+            Vec::new()
+        });
+        let mut materials = Vec::new();
+        for material in obj_materials {
+            let diffuse_texture = if material.diffuse_texture.is_empty() {
+                let [r, g, b] = material.diffuse.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+                Texture::from_color(context, [r, g, b, 255], &material.name)?
+            } else {
+                let bytes = std::fs::read(directory.join(&material.diffuse_texture))?;
+                Texture::from_bytes(context, &bytes, &material.diffuse_texture)?
+            };
+            materials.push(Material::new(device, layout, &material.name, diffuse_texture));
+        }
+
+        let meshes = models
+            .into_iter()
+            .map(|model| {
+                let obj_mesh = model.mesh;
+                let mut vertices = (0..obj_mesh.positions.len() / 3)
+                    .map(|i| Vertex {
+                        position: [
+                            obj_mesh.positions[i * 3],
+                            obj_mesh.positions[i * 3 + 1],
+                            obj_mesh.positions[i * 3 + 2],
+                        ],
+                        // OBJ puts v = 0 at the bottom of the image, wgpu at the top
+                        uv: match obj_mesh.texcoords.get(i * 2..i * 2 + 2) {
+                            Some(uv) => [uv[0], 1.0 - uv[1]],
+                            None => [0.0, 0.0],
+                        },
+                        normal: match obj_mesh.normals.get(i * 3..i * 3 + 3) {
+                            Some(normal) => [normal[0], normal[1], normal[2]],
+                            None => [0.0, 0.0, 0.0],
+                        },
+                        tangent: [0.0; 4],
+                    })
+                    .collect::<Vec<_>>();
+                if obj_mesh.normals.is_empty() {
+                    mesh::generate_normals(&mut vertices, &obj_mesh.indices);
+                }
+                mesh::generate_tangents(&mut vertices, &obj_mesh.indices);
+                let mesh = Mesh::new(device, &model.name, &vertices, &obj_mesh.indices);
+                match obj_mesh.material_id {
+                    Some(material) if material < materials.len() => mesh.with_material(material),
+                    _ => mesh,
+                }
+            })
+            .collect();
+
+        Ok(Self { meshes, materials })
+    }
+
+    /// The files `load_obj` reads for `path`: the OBJ file, the MTL files
+    /// it names, whether they exist or not, and their diffuse textures.
+    pub fn obj_files(path: &Path) -> Result<Vec<PathBuf>> {
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut files = vec![path.to_path_buf()];
+        for line in std::fs::read_to_string(path)?.lines() {
+            let mut words = line.split_whitespace();
+            // Only the first name counts, like in `tobj::load_obj`
+            let (Some("mtllib"), Some(name)) = (words.next(), words.next()) else {
+                continue;
+            };
+            let mtl = directory.join(name);
+            if let Ok((materials, _)) = tobj::load_mtl(&mtl) {
+                let textures = materials
+                    .iter()
+                    .filter(|material| !material.diffuse_texture.is_empty())
+                    .map(|material| directory.join(&material.diffuse_texture));
+                files.extend(textures);
+            }
+            files.push(mtl);
+        }
+        Ok(files)
+    }
+
+    /// The material of `mesh`. Indices past the end of `materials` count
+    /// as no material.
+    pub fn material(&self, mesh: &Mesh) -> Option<&Material> {
+        self.materials.get(mesh.material?)
+    }
+
+    /// Whether `mesh` is drawn with the PBR shader.
+    pub fn is_pbr(&self, mesh: &Mesh) -> bool {
+        self.material(mesh).is_some_and(|material| material.pbr.is_some())
+    }
+
+    /// The bind group for `mesh`'s material, or `fallback` if it has none.
+    pub fn bind_group<'a>(&'a self, mesh: &Mesh, fallback: &'a wgpu::BindGroup) -> &'a wgpu::BindGroup {
+        self.material(mesh).map_or(fallback, |material| &material.bind_group)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obj_files_include_materials_and_textures() {
+        let folder = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        let files = Model::obj_files(&folder.join("cube.obj")).unwrap();
+        let names = files
+            .iter()
+            .map(|file| file.strip_prefix(&folder).unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["cube.obj", "dirt.png", "tree.png", "cube.mtl"]);
+    }
+
+    #[test]
+    fn missing_materials_fall_back_to_the_current_texture() {
+        let context = pollster::block_on(crate::Context::new_headless()).unwrap();
+        let path = std::env::temp_dir().join(format!("learning_wgpu-no-mtl-{}.obj", std::process::id()));
+        std::fs::write(
+            &path,
+            "mtllib missing.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n",
+        )
+        .unwrap();
+        let layout = context
+            .device
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor { label: None, entries: &[] });
+        let model = Model::load_obj(&context, &layout, &path);
+        std::fs::remove_file(&path).unwrap();
+
+        let model = model.unwrap();
+        assert!(model.materials.is_empty());
+        assert_eq!(model.meshes.len(), 1);
+        assert_eq!(model.meshes[0].material, None);
+    }
+
+    #[test]
+    fn unknown_materials_fall_back_to_the_current_texture() {
+        let context = pollster::block_on(crate::Context::new_headless()).unwrap();
+        let layout = context
+            .device
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor { label: None, entries: &[] });
+        let fallback = context.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &layout,
+            entries: &[],
+        });
+        let mesh = context
+            .upload_mesh::<u16>("mesh", &[], &[])
+            .with_material(3);
+        let model = Model {
+            meshes: Vec::new(),
+            materials: Vec::new(),
+        };
+        assert!(!model.is_pbr(&mesh));
+        assert!(std::ptr::eq(model.bind_group(&mesh, &fallback), &fallback));
+    }
+}
@@ -0,0 +1,212 @@
+use wgpu::util::DeviceExt;
+
+use crate::{context::Context, error::Result, texture::Texture};
+
+/// The constant parts of a metallic-roughness material, as in glTF. Each one
+/// is multiplied with the matching texture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PbrFactors {
+    /// Linear RGBA
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Scales the X and Y of the normal texture
+    pub normal_scale: f32,
+    /// How much of the occlusion texture is applied, from 0 to 1
+    pub occlusion_strength: f32,
+    /// Linear RGB
+    pub emissive: [f32; 3],
+}
+
+impl Default for PbrFactors {
+    /// The glTF defaults: a white, fully metallic and fully rough surface
+    /// that doesn't glow.
+    fn default() -> Self {
+        Self {
+            base_color: [1.0; 4],
+            metallic: 1.0,
+            roughness: 1.0,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            emissive: [0.0; 3],
+        }
+    }
+}
+
+impl PbrFactors {
+    fn to_raw(self) -> PbrFactorsRaw {
+        let [r, g, b] = self.emissive;
+        PbrFactorsRaw {
+            base_color: self.base_color,
+            emissive: [r, g, b, 0.0],
+            params: [self.metallic, self.roughness, self.normal_scale, self.occlusion_strength],
+        }
+    }
+}
+
+/// Matches `MaterialFactors` in `pbr.wgsl`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PbrFactorsRaw {
+    base_color: [f32; 4],
+    /// `w` is unused
+    emissive: [f32; 4],
+    /// Metallic, roughness, normal scale and occlusion strength
+    params: [f32; 4],
+}
+
+/// The textures of a PBR material besides the base color, which lives in
+/// `Material::diffuse_texture`. Missing ones are replaced by a texture that
+/// leaves the factors as they are.
+#[derive(Debug, Default)]
+pub struct PbrTextures {
+    /// Roughness in the green channel, metalness in the blue channel. Linear
+    pub metallic_roughness: Option<Texture>,
+    /// Tangent space normals. Linear
+    pub normal: Option<Texture>,
+    /// Ambient occlusion in the red channel. Linear
+    pub occlusion: Option<Texture>,
+    /// sRGB
+    pub emissive: Option<Texture>,
+}
+
+/// The parts of a `Material` that only the PBR shader uses.
+pub struct PbrMaterial {
+    pub factors: PbrFactors,
+    pub metallic_roughness_texture: Texture,
+    pub normal_texture: Texture,
+    pub occlusion_texture: Texture,
+    pub emissive_texture: Texture,
+    /// Whether back faces are drawn too, lit as seen from their side
+    pub double_sided: bool,
+    factors_buffer: wgpu::Buffer,
+}
+
+impl PbrMaterial {
+    pub fn new(
+        context: &Context,
+        textures: PbrTextures,
+        factors: PbrFactors,
+        label: &str,
+    ) -> Result<Self> {
+        let linear = |color: [u8; 4], name: &str| {
+            let img = image::RgbaImage::from_pixel(1, 1, image::Rgba(color));
+            let label = format!("{} {}", label, name);
+            Texture::from_linear_image(context, &image::DynamicImage::ImageRgba8(img), Some(&label))
+        };
+        let metallic_roughness_texture = match textures.metallic_roughness {
+            Some(texture) => texture,
+            None => linear([255; 4], "Metallic Roughness")?,
+        };
+        let normal_texture = match textures.normal {
+            Some(texture) => texture,
+            // Straight up in tangent space
+            None => linear([128, 128, 255, 255], "Normal")?,
+        };
+        let occlusion_texture = match textures.occlusion {
+            Some(texture) => texture,
+            None => linear([255; 4], "Occlusion")?,
+        };
+        let emissive_texture = match textures.emissive {
+            Some(texture) => texture,
+            None => Texture::from_color(context, [255; 4], &format!("{} Emissive", label))?,
+        };
+        let factors_buffer = context.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Factors Buffer", label)),
+            contents: bytemuck::bytes_of(&factors.to_raw()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Ok(Self {
+            factors,
+            metallic_roughness_texture,
+            normal_texture,
+            occlusion_texture,
+            emissive_texture,
+            double_sided: false,
+            factors_buffer,
+        })
+    }
+
+    /// Changes the factors. Bind groups created before stay valid.
+    pub fn set_factors(&mut self, queue: &wgpu::Queue, factors: PbrFactors) {
+        self.factors = factors;
+        queue.write_buffer(&self.factors_buffer, 0, bytemuck::bytes_of(&factors.to_raw()));
+    }
+
+    /// The layout of the material bind group (group 0) of the PBR shader:
+    /// the factors at binding 0, followed by a texture and sampler pair for
+    /// the base color, metallic-roughness, normal, occlusion and emissive
+    /// textures.
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let mut entries = vec![wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }];
+        for texture in 0..5 {
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 1 + texture * 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            });
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 2 + texture * 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            });
+        }
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some("pbr_material_bind_group_layout"),
+        })
+    }
+
+    /// Binds the factors and textures to a layout created by
+    /// `bind_group_layout`.
+    pub fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        base_color_texture: &Texture,
+        label: &str,
+    ) -> wgpu::BindGroup {
+        let textures = [
+            base_color_texture,
+            &self.metallic_roughness_texture,
+            &self.normal_texture,
+            &self.occlusion_texture,
+            &self.emissive_texture,
+        ];
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: self.factors_buffer.as_entire_binding(),
+        }];
+        for (i, texture) in (0..).zip(textures) {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 1 + i * 2,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: 2 + i * 2,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            });
+        }
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &entries,
+            label: Some(label),
+        })
+    }
+}
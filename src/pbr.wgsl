@@ -0,0 +1,250 @@
+// Metallic-roughness shading as in the glTF spec: a Lambertian diffuse and a
+// Cook-Torrance specular term with the GGX distribution, the Smith-GGX
+// geometry term and Schlick's Fresnel approximation.
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+}
+
+struct CameraUniform {
+    proj: mat4x4<f32>,
+    view_pos: vec4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Matches LightRaw in light.rs, see shader.wgsl
+struct Light {
+    position: vec4<f32>,
+    direction: vec4<f32>,
+    color: vec4<f32>,
+    attenuation: vec4<f32>,
+}
+
+struct Lights {
+    ambient: vec4<f32>,
+    count: u32,
+    lights: array<Light, 16>,
+}
+@group(2) @binding(0)
+var<uniform> lights: Lights;
+
+// Matches ShadowUniform in shadow.rs, see shader.wgsl
+struct Shadows {
+    view_proj: array<mat4x4<f32>, 4>,
+    depth_bias: vec4<f32>,
+    normal_offset: vec4<f32>,
+    light_index: u32,
+    cascade_count: u32,
+    pcf_radius: u32,
+}
+@group(3) @binding(0)
+var<uniform> shadows: Shadows;
+@group(3) @binding(1)
+var shadow_map: texture_depth_2d_array;
+@group(3) @binding(2)
+var shadow_sampler: sampler_comparison;
+
+// 0 where the shadow casting light is blocked, 1 where it isn't
+fn shadow_factor(world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    // The first cascade containing the fragment is the sharpest one
+    for (var i = 0u; i < shadows.cascade_count; i += 1u) {
+        let position = world_position + normal * shadows.normal_offset[i];
+        let clip = shadows.view_proj[i] * vec4<f32>(position, 1.0);
+        let coords = clip.xyz / clip.w;
+        let uv = coords.xy * vec2<f32>(0.5, -0.5) + 0.5;
+        if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || coords.z > 1.0 {
+            continue;
+        }
+
+        // Percentage-closer filtering: average the comparisons around uv
+        let depth = coords.z - shadows.depth_bias[i];
+        let texel = 1.0 / vec2<f32>(textureDimensions(shadow_map));
+        let radius = i32(shadows.pcf_radius);
+        var lit = 0.0;
+        for (var y = -radius; y <= radius; y += 1) {
+            for (var x = -radius; x <= radius; x += 1) {
+                let offset = vec2<f32>(f32(x), f32(y)) * texel;
+                lit += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, i32(i), depth);
+            }
+        }
+        let samples = 2 * radius + 1;
+        return lit / f32(samples * samples);
+    }
+    return 1.0;
+}
+
+struct VInput {
+    @location(0) pos: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
+}
+
+struct VOutput {
+    @builtin(position) vertices: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_position: vec3<f32>,
+    // w: handedness of the bitangent
+    @location(3) world_tangent: vec4<f32>,
+}
+
+@vertex
+fn vertex_main(input: VInput, instance: InstanceInput) -> VOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let m = mat3x3<f32>(model_matrix[0].xyz, model_matrix[1].xyz, model_matrix[2].xyz);
+    let normal_matrix = mat3x3<f32>(cross(m[1], m[2]), cross(m[2], m[0]), cross(m[0], m[1]));
+
+    let world_position = model_matrix * vec4<f32>(input.pos, 1.0);
+    var out: VOutput;
+    out.uv = input.uv;
+    out.world_normal = normal_matrix * input.normal;
+    // Tangents lie in the surface, so they transform like positions
+    out.world_tangent = vec4<f32>(m * input.tangent.xyz, input.tangent.w);
+    out.world_position = world_position.xyz;
+    out.vertices = camera.proj * world_position;
+    return out;
+}
+
+// Matches PbrFactorsRaw in pbr.rs
+struct MaterialFactors {
+    base_color: vec4<f32>,
+    emissive: vec4<f32>,
+    // metallic, roughness, normal scale, occlusion strength
+    params: vec4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> material: MaterialFactors;
+@group(0) @binding(1)
+var base_color_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var base_color_sampler: sampler;
+@group(0) @binding(3)
+var metallic_roughness_texture: texture_2d<f32>;
+@group(0) @binding(4)
+var metallic_roughness_sampler: sampler;
+@group(0) @binding(5)
+var normal_texture: texture_2d<f32>;
+@group(0) @binding(6)
+var normal_sampler: sampler;
+@group(0) @binding(7)
+var occlusion_texture: texture_2d<f32>;
+@group(0) @binding(8)
+var occlusion_sampler: sampler;
+@group(0) @binding(9)
+var emissive_texture: texture_2d<f32>;
+@group(0) @binding(10)
+var emissive_sampler: sampler;
+
+let PI: f32 = 3.14159265;
+// Reflectance of dielectrics at normal incidence
+let DIELECTRIC_F0: f32 = 0.04;
+// Perfectly smooth surfaces would have infinitely small highlights
+let MIN_ROUGHNESS: f32 = 0.04;
+
+// GGX / Trowbridge-Reitz normal distribution
+fn distribution(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * d * d);
+}
+
+// Height-correlated Smith-GGX visibility, which already includes the
+// 1 / (4 n.l n.v) of the Cook-Torrance denominator
+fn visibility(n_dot_l: f32, n_dot_v: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - alpha2) + alpha2);
+    let ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - alpha2) + alpha2);
+    let ggx = ggx_v + ggx_l;
+    if ggx > 0.0 {
+        return 0.5 / ggx;
+    }
+    return 0.0;
+}
+
+fn fresnel(f0: vec3<f32>, v_dot_h: f32) -> vec3<f32> {
+    return f0 + (vec3<f32>(1.0) - f0) * pow(1.0 - v_dot_h, 5.0);
+}
+
+@fragment
+fn fragment_main(input: VOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    let base_color = textureSample(base_color_texture, base_color_sampler, input.uv) * material.base_color;
+    let emissive = textureSample(emissive_texture, emissive_sampler, input.uv).rgb * material.emissive.rgb;
+    let metallic_roughness = textureSample(metallic_roughness_texture, metallic_roughness_sampler, input.uv);
+    let tangent_normal = textureSample(normal_texture, normal_sampler, input.uv).xyz * 2.0 - 1.0;
+    let occlusion_sample = textureSample(occlusion_texture, occlusion_sampler, input.uv).r;
+    // Without any lights the scene is drawn unlit, like in shader.wgsl
+    if lights.count == 0u {
+        return vec4<f32>(base_color.rgb + emissive, base_color.a);
+    }
+
+    let metallic = clamp(material.params.x * metallic_roughness.b, 0.0, 1.0);
+    let roughness = clamp(material.params.y * metallic_roughness.g, MIN_ROUGHNESS, 1.0);
+    let alpha = roughness * roughness;
+    let occlusion = 1.0 + material.params.w * (occlusion_sample - 1.0);
+
+    // Move the normal from tangent space into world space
+    // Back faces are only drawn for double sided materials, and are lit
+    // as seen from their side
+    let geometric_normal = select(-1.0, 1.0, front_facing) * normalize(input.world_normal);
+    let tangent = normalize(input.world_tangent.xyz - geometric_normal * dot(geometric_normal, input.world_tangent.xyz));
+    let bitangent = cross(geometric_normal, tangent) * input.world_tangent.w;
+    let scaled_normal = tangent_normal * vec3<f32>(material.params.z, material.params.z, 1.0);
+    let normal = normalize(mat3x3<f32>(tangent, bitangent, geometric_normal) * scaled_normal);
+
+    let view_dir = normalize(camera.view_pos.xyz - input.world_position);
+    let shadow = shadow_factor(input.world_position, geometric_normal);
+    let n_dot_v = clamp(abs(dot(normal, view_dir)), 0.001, 1.0);
+    let f0 = mix(vec3<f32>(DIELECTRIC_F0), base_color.rgb, metallic);
+    let diffuse_color = base_color.rgb * (1.0 - metallic);
+
+    var color = vec3<f32>(0.0);
+    for (var i = 0u; i < lights.count; i += 1u) {
+        let light = lights.lights[i];
+        let kind = u32(light.position.w);
+
+        var light_dir: vec3<f32>;
+        var strength = light.color.w;
+        if i == shadows.light_index {
+            strength *= shadow;
+        }
+        if kind == 0u {
+            light_dir = -light.direction.xyz;
+        } else {
+            let to_light = light.position.xyz - input.world_position;
+            let distance = length(to_light);
+            light_dir = to_light / distance;
+            let a = light.attenuation;
+            strength /= a.x + a.y * distance + a.z * distance * distance;
+            if kind == 2u {
+                let cos_angle = dot(-light_dir, light.direction.xyz);
+                strength *= smoothstep(a.w, light.direction.w, cos_angle);
+            }
+        }
+
+        let n_dot_l = dot(normal, light_dir);
+        if n_dot_l <= 0.0 {
+            continue;
+        }
+        let half_dir = normalize(light_dir + view_dir);
+        let n_dot_h = max(dot(normal, half_dir), 0.0);
+        let v_dot_h = max(dot(view_dir, half_dir), 0.0);
+
+        let f = fresnel(f0, v_dot_h);
+        let specular = f * distribution(n_dot_h, alpha) * visibility(n_dot_l, n_dot_v, alpha);
+        // Light reflected at the surface doesn't reach the diffuse layer
+        let diffuse = (vec3<f32>(1.0) - f) * diffuse_color / PI;
+        color += (diffuse + specular) * light.color.rgb * strength * n_dot_l;
+    }
+    let ambient = lights.ambient.rgb * base_color.rgb * occlusion;
+    return vec4<f32>(ambient + color + emissive, base_color.a);
+}
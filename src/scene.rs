@@ -0,0 +1,292 @@
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use cgmath::{Matrix4, SquareMatrix};
+
+use crate::{
+    context::Context,
+    error::Result,
+    instance::InstanceRaw,
+    mesh::{self, Mesh},
+    model::{Material, Model},
+    pbr::{PbrFactors, PbrMaterial, PbrTextures},
+    texture::Texture,
+    vertex::Vertex,
+};
+
+/// A mesh of a `Scene` together with every place it is drawn at.
+pub struct SceneObject {
+    pub name: String,
+    /// The sub-meshes (glTF primitives) of this object in `Scene::model`
+    pub meshes: Range<usize>,
+    /// Full model matrices, as the transforms of glTF nodes can be skewed
+    pub instances: Vec<InstanceRaw>,
+    pub instance_buffer: wgpu::Buffer,
+    /// Whether the instances mirror the meshes, which turns the winding of
+    /// their triangles around. Only PBR materials take this into account
+    pub mirrored: bool,
+}
+
+/// Objects imported from a glTF file.
+///
+/// All meshes and materials of the file live in a single `Model`, so
+/// materials shared between meshes are only uploaded once.
+pub struct Scene {
+    pub model: Model,
+    pub objects: Vec<SceneObject>,
+}
+
+impl Scene {
+    /// Imports a glTF (`.gltf`) or binary glTF (`.glb`) file.
+    ///
+    /// Buffers and images can be embedded in the file, in data URIs or in
+    /// files next to it. Every mesh becomes a `SceneObject` with one
+    /// instance for each node of the default scene that references it, at
+    /// the node's world transform. Only triangle primitives are imported.
+    ///
+    /// Materials are drawn with the PBR shader, so `layout` is the one
+    /// created by `PbrMaterial::bind_group_layout`. Every texture is read
+    /// with the set of texture coordinates of the base color texture and
+    /// the sampler settings of the file are ignored.
+    pub fn load_gltf<P: AsRef<Path>>(
+        context: &Context,
+        layout: &wgpu::BindGroupLayout,
+        path: P,
+    ) -> Result<Self> {
+        let device = &context.device;
+        let (document, buffers, images) = gltf::import(path)?;
+
+        let mut materials = document
+            .materials()
+            .map(|material| load_material(context, layout, &images, &material))
+            .collect::<Result<Vec<_>>>()?;
+        // Primitives without a material use the glTF default material,
+        // which is plain white
+        let default_material = materials.len();
+        let pbr = PbrMaterial::new(context, PbrTextures::default(), PbrFactors::default(), "Default Material")?;
+        let base_color_texture = Texture::from_color(context, [255; 4], "Default Material")?;
+        materials.push(Material::new_pbr(device, layout, "Default Material", base_color_texture, pbr));
+
+        let mut meshes = Vec::new();
+        let mut mesh_ranges = Vec::new();
+        for gltf_mesh in document.meshes() {
+            let name = gltf_mesh.name().unwrap_or("glTF Mesh");
+            let start = meshes.len();
+            for primitive in gltf_mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    continue;
+                }
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                let positions = match reader.read_positions() {
+                    Some(positions) => positions,
+                    None => continue,
+                };
+                // glTF puts the origin of texture coordinates at the top
+                // left, like wgpu
+                let tex_coord = primitive
+                    .material()
+                    .pbr_metallic_roughness()
+                    .base_color_texture()
+                    .map_or(0, |info| info.tex_coord());
+                let mut uvs = reader.read_tex_coords(tex_coord).map(|uvs| uvs.into_f32());
+                let mut normals = reader.read_normals();
+                let mut tangents = reader.read_tangents();
+                let mut vertices = positions
+                    .map(|position| Vertex {
+                        position,
+                        uv: uvs.as_mut().and_then(Iterator::next).unwrap_or([0.0, 0.0]),
+                        normal: normals.as_mut().and_then(Iterator::next).unwrap_or([0.0; 3]),
+                        tangent: tangents.as_mut().and_then(Iterator::next).unwrap_or([0.0; 4]),
+                    })
+                    .collect::<Vec<_>>();
+                let indices = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+                    None => (0..vertices.len() as u32).collect(),
+                };
+                // Generated the way the glTF spec suggests when missing
+                if reader.read_normals().is_none() {
+                    mesh::generate_normals(&mut vertices, &indices);
+                }
+                if reader.read_tangents().is_none() {
+                    mesh::generate_tangents(&mut vertices, &indices);
+                }
+                let material = primitive.material().index().unwrap_or(default_material);
+                meshes.push(Mesh::new(device, name, &vertices, &indices).with_material(material));
+            }
+            mesh_ranges.push((name.to_string(), start..meshes.len()));
+        }
+
+        let mut instances = vec![Vec::new(); mesh_ranges.len()];
+        let scene = document
+            .default_scene()
+            .or_else(|| document.scenes().next());
+        for node in scene.iter().flat_map(|scene| scene.nodes()) {
+            collect_instances(&node, Matrix4::identity(), &mut instances);
+        }
+
+        let mut objects = Vec::new();
+        for ((name, meshes), transforms) in mesh_ranges.into_iter().zip(instances) {
+            // Mirroring transforms need the other winding, so their
+            // instances go into an object of their own
+            let (mirrored, unmirrored): (Vec<_>, Vec<_>) =
+                transforms.into_iter().partition(|transform| transform.determinant() < 0.0);
+            for (transforms, mirrored) in [(unmirrored, false), (mirrored, true)] {
+                if meshes.is_empty() || transforms.is_empty() {
+                    continue;
+                }
+                let instances = transforms
+                    .into_iter()
+                    .map(InstanceRaw::from_transform)
+                    .collect::<Vec<_>>();
+                objects.push(SceneObject {
+                    instance_buffer: InstanceRaw::upload(device, &instances),
+                    name: name.clone(),
+                    meshes: meshes.clone(),
+                    instances,
+                    mirrored,
+                });
+            }
+        }
+
+        Ok(Self {
+            model: Model { meshes, materials },
+            objects,
+        })
+    }
+
+    /// The files `load_gltf` reads for `path`: the glTF file and the
+    /// buffers and images it refers to by relative URI.
+    pub fn gltf_files(path: &Path) -> Result<Vec<PathBuf>> {
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        let gltf = gltf::Gltf::open(path)?;
+        let buffers = gltf.buffers().filter_map(|buffer| match buffer.source() {
+            gltf::buffer::Source::Uri(uri) => Some(uri),
+            gltf::buffer::Source::Bin => None,
+        });
+        let images = gltf.images().filter_map(|image| match image.source() {
+            gltf::image::Source::Uri { uri, .. } => Some(uri),
+            gltf::image::Source::View { .. } => None,
+        });
+        let mut files = vec![path.to_path_buf()];
+        // URIs with a scheme, like embedded data, aren't relative paths
+        for uri in buffers.chain(images).filter(|uri| !uri.contains(':')) {
+            if let Ok(decoded) = urlencoding::decode(uri) {
+                files.push(directory.join(&*decoded));
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Uploads the textures and factors of a metallic-roughness material.
+fn load_material(
+    context: &Context,
+    layout: &wgpu::BindGroupLayout,
+    images: &[gltf::image::Data],
+    material: &gltf::Material,
+) -> Result<Material> {
+    let name = material.name().unwrap_or("glTF Material");
+    let srgb = |texture: gltf::Texture| {
+        let image = to_dynamic_image(&images[texture.source().index()])?;
+        Texture::from_image(context, &image, Some(name))
+    };
+    let linear = |texture: gltf::Texture| {
+        let image = to_dynamic_image(&images[texture.source().index()])?;
+        Texture::from_linear_image(context, &image, Some(name))
+    };
+
+    let pbr = material.pbr_metallic_roughness();
+    let base_color_texture = match pbr.base_color_texture() {
+        Some(info) => srgb(info.texture())?,
+        None => Texture::from_color(context, [255; 4], name)?,
+    };
+    let normal = material.normal_texture();
+    let occlusion = material.occlusion_texture();
+    let factors = PbrFactors {
+        base_color: pbr.base_color_factor(),
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        normal_scale: normal.as_ref().map_or(1.0, |normal| normal.scale()),
+        occlusion_strength: occlusion.as_ref().map_or(1.0, |occlusion| occlusion.strength()),
+        emissive: material.emissive_factor(),
+    };
+    let textures = PbrTextures {
+        metallic_roughness: pbr
+            .metallic_roughness_texture()
+            .map(|info| linear(info.texture()))
+            .transpose()?,
+        normal: normal.map(|normal| linear(normal.texture())).transpose()?,
+        occlusion: occlusion.map(|occlusion| linear(occlusion.texture())).transpose()?,
+        emissive: material
+            .emissive_texture()
+            .map(|info| srgb(info.texture()))
+            .transpose()?,
+    };
+    let mut pbr = PbrMaterial::new(context, textures, factors, name)?;
+    pbr.double_sided = material.double_sided();
+    Ok(Material::new_pbr(&context.device, layout, name, base_color_texture, pbr))
+}
+
+/// Walks the node hierarchy, adding the world transform of every mesh
+/// reference.
+fn collect_instances(
+    node: &gltf::Node,
+    parent_transform: Matrix4<f32>,
+    instances: &mut [Vec<Matrix4<f32>>],
+) {
+    let transform = parent_transform * Matrix4::from(node.transform().matrix());
+    if let Some(mesh) = node.mesh() {
+        instances[mesh.index()].push(transform);
+    }
+    for child in node.children() {
+        collect_instances(&child, transform, instances);
+    }
+}
+
+/// Wraps the pixels decoded by the glTF importer in an image, so they can go
+/// through `Texture::from_image` like every other texture.
+fn to_dynamic_image(data: &gltf::image::Data) -> Result<image::DynamicImage> {
+    use gltf::image::Format;
+    use image::DynamicImage;
+
+    let (width, height) = (data.width, data.height);
+    let pixels = || data.pixels.clone();
+    let u16s = || {
+        data.pixels
+            .chunks_exact(2)
+            .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+            .collect::<Vec<_>>()
+    };
+    let f32s = || {
+        data.pixels
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect::<Vec<_>>()
+    };
+    let image = match data.format {
+        Format::R8 => image::ImageBuffer::from_raw(width, height, pixels()).map(DynamicImage::ImageLuma8),
+        Format::R8G8 => image::ImageBuffer::from_raw(width, height, pixels()).map(DynamicImage::ImageLumaA8),
+        Format::R8G8B8 => image::ImageBuffer::from_raw(width, height, pixels()).map(DynamicImage::ImageRgb8),
+        Format::R8G8B8A8 => image::ImageBuffer::from_raw(width, height, pixels()).map(DynamicImage::ImageRgba8),
+        Format::R16 => image::ImageBuffer::from_raw(width, height, u16s()).map(DynamicImage::ImageLuma16),
+        Format::R16G16 => image::ImageBuffer::from_raw(width, height, u16s()).map(DynamicImage::ImageLumaA16),
+        Format::R16G16B16 => image::ImageBuffer::from_raw(width, height, u16s()).map(DynamicImage::ImageRgb16),
+        Format::R16G16B16A16 => {
+            image::ImageBuffer::from_raw(width, height, u16s()).map(DynamicImage::ImageRgba16)
+        }
+        Format::R32G32B32FLOAT => {
+            image::ImageBuffer::from_raw(width, height, f32s()).map(DynamicImage::ImageRgb32F)
+        }
+        Format::R32G32B32A32FLOAT => {
+            image::ImageBuffer::from_raw(width, height, f32s()).map(DynamicImage::ImageRgba32F)
+        }
+    };
+    image.ok_or_else(|| {
+        image::ImageError::Parameter(image::error::ParameterError::from_kind(
+            image::error::ParameterErrorKind::DimensionMismatch,
+        ))
+        .into()
+    })
+}
@@ -3,22 +3,93 @@ struct InstanceInput {
     @location(6) model_matrix_1: vec4<f32>,
     @location(7) model_matrix_2: vec4<f32>,
     @location(8) model_matrix_3: vec4<f32>,
+    @location(9) texture_layer: u32,
 }
 
 struct CameraUniform {
     proj: mat4x4<f32>,
+    view_pos: vec4<f32>,
 };
 @group(1) @binding(0)
 var<uniform> camera: CameraUniform;
 
+// Matches LightRaw in light.rs
+struct Light {
+    // w: 0 directional, 1 point, 2 spot
+    position: vec4<f32>,
+    // w: cosine of the inner angle of spot lights
+    direction: vec4<f32>,
+    // w: intensity
+    color: vec4<f32>,
+    // constant, linear, quadratic, w: cosine of the outer angle of spot lights
+    attenuation: vec4<f32>,
+}
+
+struct Lights {
+    ambient: vec4<f32>,
+    count: u32,
+    lights: array<Light, 16>,
+}
+@group(2) @binding(0)
+var<uniform> lights: Lights;
+
+// Matches ShadowUniform in shadow.rs
+struct Shadows {
+    view_proj: array<mat4x4<f32>, 4>,
+    depth_bias: vec4<f32>,
+    normal_offset: vec4<f32>,
+    light_index: u32,
+    cascade_count: u32,
+    pcf_radius: u32,
+}
+@group(3) @binding(0)
+var<uniform> shadows: Shadows;
+@group(3) @binding(1)
+var shadow_map: texture_depth_2d_array;
+@group(3) @binding(2)
+var shadow_sampler: sampler_comparison;
+
+// 0 where the shadow casting light is blocked, 1 where it isn't
+fn shadow_factor(world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    // The first cascade containing the fragment is the sharpest one
+    for (var i = 0u; i < shadows.cascade_count; i += 1u) {
+        let position = world_position + normal * shadows.normal_offset[i];
+        let clip = shadows.view_proj[i] * vec4<f32>(position, 1.0);
+        let coords = clip.xyz / clip.w;
+        let uv = coords.xy * vec2<f32>(0.5, -0.5) + 0.5;
+        if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || coords.z > 1.0 {
+            continue;
+        }
+
+        // Percentage-closer filtering: average the comparisons around uv
+        let depth = coords.z - shadows.depth_bias[i];
+        let texel = 1.0 / vec2<f32>(textureDimensions(shadow_map));
+        let radius = i32(shadows.pcf_radius);
+        var lit = 0.0;
+        for (var y = -radius; y <= radius; y += 1) {
+            for (var x = -radius; x <= radius; x += 1) {
+                let offset = vec2<f32>(f32(x), f32(y)) * texel;
+                lit += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, i32(i), depth);
+            }
+        }
+        let samples = 2 * radius + 1;
+        return lit / f32(samples * samples);
+    }
+    return 1.0;
+}
+
 struct VInput {
     @location(0) pos: vec3<f32>,
-    @location(1) uv: vec2<f32>
+    @location(1) uv: vec2<f32>,
+    @location(2) normal: vec3<f32>,
 }
 
 struct VOutput {
     @builtin(position) vertices: vec4<f32>,
     @location(0) uv: vec2<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_position: vec3<f32>,
+    @location(3) @interpolate(flat) texture_layer: u32,
 }
 
 @vertex
@@ -29,9 +100,19 @@ fn vertex_main(input: VInput, instance: InstanceInput) -> VOutput {
         instance.model_matrix_2,
         instance.model_matrix_3,
     );
+    // Normals have to be transformed by the inverse transpose of the model
+    // matrix to stay perpendicular under non-uniform scaling. The cofactor
+    // matrix is the same up to a scale, which the normalize takes care of.
+    let m = mat3x3<f32>(model_matrix[0].xyz, model_matrix[1].xyz, model_matrix[2].xyz);
+    let normal_matrix = mat3x3<f32>(cross(m[1], m[2]), cross(m[2], m[0]), cross(m[0], m[1]));
+
+    let world_position = model_matrix * vec4<f32>(input.pos, 1.0);
     var out: VOutput;
     out.uv = input.uv;
-    out.vertices = camera.proj * model_matrix * vec4<f32>(input.pos, 1.0);
+    out.world_normal = normal_matrix * input.normal;
+    out.world_position = world_position.xyz;
+    out.texture_layer = instance.texture_layer;
+    out.vertices = camera.proj * world_position;
     return out;
 }
 
@@ -39,9 +120,70 @@ fn vertex_main(input: VInput, instance: InstanceInput) -> VOutput {
 var texture: texture_2d<f32>;
 @group(0)@binding(1)
 var texture_sampler: sampler;
+// Bound instead of `texture` by the pipeline using fragment_array, see
+// TextureLoad::Array
+@group(0) @binding(0)
+var texture_array: texture_2d_array<f32>;
+
+// Blinn-Phong material, the same for every surface for now
+let SPECULAR_STRENGTH: f32 = 0.5;
+let SHININESS: f32 = 32.0;
+
+// Lights the surface color of a fragment
+fn shade(input: VOutput, albedo: vec4<f32>) -> vec4<f32> {
+    // Without any lights the scene is drawn unlit
+    if lights.count == 0u {
+        return albedo;
+    }
+
+    let normal = normalize(input.world_normal);
+    let view_dir = normalize(camera.view_pos.xyz - input.world_position);
+    let shadow = shadow_factor(input.world_position, normal);
+    var diffuse_sum = lights.ambient.rgb;
+    var specular_sum = vec3<f32>(0.0);
+    for (var i = 0u; i < lights.count; i += 1u) {
+        let light = lights.lights[i];
+        let kind = u32(light.position.w);
+
+        var light_dir: vec3<f32>;
+        var strength = light.color.w;
+        if i == shadows.light_index {
+            strength *= shadow;
+        }
+        if kind == 0u {
+            light_dir = -light.direction.xyz;
+        } else {
+            let to_light = light.position.xyz - input.world_position;
+            let distance = length(to_light);
+            light_dir = to_light / distance;
+            let a = light.attenuation;
+            strength /= a.x + a.y * distance + a.z * distance * distance;
+            if kind == 2u {
+                // Fade out between the inner and outer angle of the cone
+                let cos_angle = dot(-light_dir, light.direction.xyz);
+                strength *= smoothstep(a.w, light.direction.w, cos_angle);
+            }
+        }
+
+        let diffuse = max(dot(normal, light_dir), 0.0);
+        let half_dir = normalize(light_dir + view_dir);
+        var specular = 0.0;
+        if diffuse > 0.0 {
+            specular = SPECULAR_STRENGTH * pow(max(dot(normal, half_dir), 0.0), SHININESS);
+        }
+        diffuse_sum += light.color.rgb * strength * diffuse;
+        specular_sum += light.color.rgb * strength * specular;
+    }
+    // Highlights reflect the light's color, not the surface's
+    return vec4<f32>(albedo.rgb * diffuse_sum + specular_sum, albedo.a);
+}
 
 @fragment
 fn fragment_main(input: VOutput) -> @location(0) vec4<f32> {
-    return textureSample(texture, texture_sampler, input.uv);
-} 
+    return shade(input, textureSample(texture, texture_sampler, input.uv));
+}
 
+@fragment
+fn fragment_array(input: VOutput) -> @location(0) vec4<f32> {
+    return shade(input, textureSample(texture_array, texture_sampler, input.uv, i32(input.texture_layer)));
+}
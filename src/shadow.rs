@@ -0,0 +1,560 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Transform, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    camera::{Camera, OPENGL_TO_WGPU_MATRIX},
+    context::Context,
+    error::Result,
+    instance::InstanceRaw,
+    texture::Texture,
+    vertex::{Vertex, VertexLayout},
+};
+
+/// How many slices the view frustum can be split into. Each one gets its
+/// own layer in the shadow map.
+pub const MAX_CASCADES: usize = 4;
+
+/// Casters this far outside of a cascade, towards the light, still cast
+/// shadows into it.
+const CASTER_MARGIN: f32 = 20.0;
+
+/// Depth bias added per unit of depth slope when rendering the shadow map.
+const SLOPE_BIAS: f32 = 2.0;
+
+/// How the shadows of the first directional light are rendered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSettings {
+    /// Width and height of the depth map of each cascade, in texels.
+    /// Clamped to the largest 2D texture the device supports
+    pub resolution: u32,
+    /// How many slices the view frustum is split into, from 1 to
+    /// `MAX_CASCADES`. Close slices are smaller, so they get sharper
+    /// shadows.
+    pub cascades: u32,
+    /// How far in front of the camera shadows are drawn, unless the
+    /// camera's `zfar` is closer
+    pub max_distance: f32,
+    /// Where the slices are split, from evenly spaced (0) to growing
+    /// logarithmically with the distance (1)
+    pub split_lambda: f32,
+    /// How far, in world units, a surface has to be behind the closest
+    /// one to the light to be in shadow. Too little gives shadow acne,
+    /// too much detaches shadows from their casters
+    pub depth_bias: f32,
+    /// How far surfaces are pushed along their normal before looking them
+    /// up in the shadow map, in shadow map texels. Fights acne on surfaces
+    /// at a grazing angle to the light
+    pub normal_bias: f32,
+    /// Texels sampled around each lookup in every direction, to soften the
+    /// shadow edges (percentage-closer filtering). 0 samples a single texel,
+    /// 1 a 3x3 block and so on
+    pub pcf_radius: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            cascades: 3,
+            max_distance: 50.0,
+            split_lambda: 0.75,
+            depth_bias: 0.02,
+            normal_bias: 2.0,
+            pcf_radius: 1,
+        }
+    }
+}
+
+/// Matches `Shadows` in the shaders.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    view_proj: [[[f32; 4]; 4]; MAX_CASCADES],
+    /// `ShadowSettings::depth_bias` in the depth units of each cascade
+    depth_bias: [f32; MAX_CASCADES],
+    /// `ShadowSettings::normal_bias` in world units for each cascade
+    normal_offset: [f32; MAX_CASCADES],
+    /// Index of the shadow casting light in the light uniform
+    light_index: u32,
+    /// 0 while no light casts shadows
+    cascade_count: u32,
+    pcf_radius: u32,
+    _padding: u32,
+}
+
+/// The orthographic projection a light casts one slice of the view frustum
+/// with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cascade {
+    view_proj: Matrix4<f32>,
+    /// Size of a shadow map texel in world units
+    texel_size: f32,
+    /// World units covered by the depth range of the projection
+    depth_range: f32,
+}
+
+/// Distances from the eye at which the view frustum is split, starting
+/// with `near` and ending with `far`. Mixes evenly spaced and logarithmic
+/// splits with `lambda`, as in "Parallel-Split Shadow Maps".
+fn cascade_splits(near: f32, far: f32, count: usize, lambda: f32) -> Vec<f32> {
+    (0..=count)
+        .map(|i| {
+            let t = i as f32 / count as f32;
+            let uniform = near + (far - near) * t;
+            let logarithmic = near * (far / near).powf(t);
+            lambda * logarithmic + (1.0 - lambda) * uniform
+        })
+        .collect()
+}
+
+/// Fits a projection along `direction` around the frustum slice with the
+/// given `corners`.
+///
+/// The projection covers the bounding sphere of the slice, so its size
+/// doesn't change when the camera turns, and it only moves in whole texels.
+/// Both keep the shadow edges from shimmering while the camera moves.
+fn fit_cascade(corners: &[Point3<f32>; 8], direction: Vector3<f32>, resolution: u32) -> Cascade {
+    let direction = direction.normalize();
+    let center = Point3::centroid(corners);
+    let radius = corners
+        .iter()
+        .map(|corner| (corner - center).magnitude())
+        .fold(0.0, f32::max);
+    // Rounding up keeps the size from changing with floating point noise
+    let radius = (radius * 16.0).ceil() / 16.0;
+    let texel_size = radius * 2.0 / resolution as f32;
+
+    let up = if direction.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+    let rotation = Matrix4::look_to_rh(Point3::new(0.0, 0.0, 0.0), direction, up);
+    let mut light_center = rotation.transform_point(center);
+    light_center.x = (light_center.x / texel_size).floor() * texel_size;
+    light_center.y = (light_center.y / texel_size).floor() * texel_size;
+    let center = rotation
+        .inverse_transform()
+        .unwrap_or_else(Matrix4::identity)
+        .transform_point(light_center);
+
+    let depth_range = radius * 2.0 + CASTER_MARGIN;
+    let eye = center - direction * (radius + CASTER_MARGIN);
+    let view = Matrix4::look_to_rh(eye, direction, up);
+    let projection = cgmath::ortho(-radius, radius, -radius, radius, 0.0, depth_range);
+    Cascade {
+        view_proj: OPENGL_TO_WGPU_MATRIX * projection * view,
+        texel_size,
+        depth_range,
+    }
+}
+
+/// Splits the view frustum of `camera` into `settings.cascades` slices and
+/// fits a projection along `direction` to each of them.
+fn fit_cascades(camera: &Camera, direction: Vector3<f32>, settings: &ShadowSettings) -> Vec<Cascade> {
+    let count = (settings.cascades as usize).clamp(1, MAX_CASCADES);
+    let far = settings.max_distance.min(camera.zfar);
+    let splits = cascade_splits(camera.znear, far, count, settings.split_lambda);
+    splits
+        .windows(2)
+        .map(|split| {
+            let corners = camera.frustum_corners(split[0], split[1]);
+            fit_cascade(&corners, direction, settings.resolution)
+        })
+        .collect()
+}
+
+/// A cascade's layer of the shadow map and the projection it is rendered
+/// with.
+struct CascadeTarget {
+    view: wgpu::TextureView,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+/// `settings` with a resolution `device` can create a texture of.
+fn clamp_resolution(device: &wgpu::Device, settings: ShadowSettings) -> ShadowSettings {
+    ShadowSettings {
+        resolution: settings.resolution.clamp(1, device.limits().max_texture_dimension_2d),
+        ..settings
+    }
+}
+
+/// The layers of the shadow map: one per cascade, but at least
+/// `min_layers`.
+fn layer_count(settings: &ShadowSettings, min_layers: u32) -> u32 {
+    (settings.cascades as usize).clamp(1, MAX_CASCADES).max(min_layers as usize) as u32
+}
+
+/// Cascaded shadow maps for the first directional light.
+///
+/// `update` fits the cascades to the camera every frame, then the scene is
+/// drawn into every layer of `cascades` with `pipeline` before the main
+/// pass samples them through `bind_group`.
+pub struct ShadowMaps {
+    settings: ShadowSettings,
+    /// Array texture with a layer for each cascade. Only kept alive
+    /// alongside the bind groups
+    _texture: Texture,
+    cascades: Vec<CascadeTarget>,
+    /// The fewest layers the texture can have, see `new`
+    min_layers: u32,
+    /// Cascades in use since the last `update`
+    active_cascades: usize,
+    uniform_buffer: wgpu::Buffer,
+    cascade_bind_group_layout: wgpu::BindGroupLayout,
+    pub pipeline: wgpu::RenderPipeline,
+    /// Layout of the shadow bind group (group 3) of the main pipelines
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ShadowMaps {
+    pub async fn new(context: &Context, settings: ShadowSettings) -> Result<Self> {
+        let device = &context.device;
+        let settings = clamp_resolution(device, settings);
+        // The GL backend turns textures with a single layer into 2D
+        // textures, which can't be viewed as arrays
+        let min_layers = match context.adapter.get_info().backend {
+            wgpu::Backend::Gl => 2,
+            _ => 1,
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Buffer"),
+            contents: bytemuck::bytes_of(&<ShadowUniform as bytemuck::Zeroable>::zeroed()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ],
+            label: Some("shadow_bind_group_layout"),
+        });
+        let cascade_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("shadow_cascade_bind_group_layout"),
+        });
+
+        let shader = context
+            .create_shader_module("Shadow Shader", include_str!("shadow.wgsl"))
+            .await?;
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            push_constant_ranges: &[],
+            bind_group_layouts: &[&cascade_bind_group_layout],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vertex_main",
+                buffers: &[Vertex::desc(), InstanceRaw::desc()],
+            },
+            // Only the depth is needed
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // Single sided meshes like the square cast shadows from
+                // both sides
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                // Surfaces at a grazing angle to the light cover a range of
+                // depths within a single texel, so they need more bias
+                bias: wgpu::DepthBiasState {
+                    constant: 0,
+                    slope_scale: SLOPE_BIAS,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let (texture, cascades, bind_group) = Self::create_targets(
+            device,
+            settings.resolution,
+            layer_count(&settings, min_layers),
+            &uniform_buffer,
+            &bind_group_layout,
+            &cascade_bind_group_layout,
+        );
+        Ok(Self {
+            settings,
+            _texture: texture,
+            cascades,
+            min_layers,
+            active_cascades: 0,
+            uniform_buffer,
+            cascade_bind_group_layout,
+            pipeline,
+            bind_group_layout,
+            bind_group,
+        })
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        resolution: u32,
+        layers: u32,
+        uniform_buffer: &wgpu::Buffer,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        cascade_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> (Texture, Vec<CascadeTarget>, wgpu::BindGroup) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow Map"),
+            size: wgpu::Extent3d {
+                width: resolution,
+                height: resolution,
+                depth_or_array_layers: layers,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        // Linear filtering compares the four closest texels and blends the
+        // results, which smooths the edges even without PCF
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let cascades = (0..layers)
+            .map(|layer| {
+                let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Shadow Cascade Buffer"),
+                    size: std::mem::size_of::<[[f32; 4]; 4]>() as wgpu::BufferAddress,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: cascade_bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                    label: Some("shadow_cascade_bind_group"),
+                });
+                let view = texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Shadow Cascade"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: std::num::NonZeroU32::new(1),
+                    ..Default::default()
+                });
+                CascadeTarget {
+                    view,
+                    buffer,
+                    bind_group,
+                }
+            })
+            .collect();
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("shadow_bind_group"),
+        });
+        let texture = Texture {
+            texture,
+            view,
+            sampler,
+        };
+        (texture, cascades, bind_group)
+    }
+
+    pub fn settings(&self) -> &ShadowSettings {
+        &self.settings
+    }
+
+    /// Takes effect with the next `update`. Changing the resolution or the
+    /// number of cascades recreates the shadow map.
+    pub fn set_settings(&mut self, device: &wgpu::Device, settings: ShadowSettings) {
+        let settings = clamp_resolution(device, settings);
+        let layers = layer_count(&settings, self.min_layers);
+        if settings.resolution != self.settings.resolution || layers != self.cascades.len() as u32 {
+            let (texture, cascades, bind_group) = Self::create_targets(
+                device,
+                settings.resolution,
+                layers,
+                &self.uniform_buffer,
+                &self.bind_group_layout,
+                &self.cascade_bind_group_layout,
+            );
+            self._texture = texture;
+            self.cascades = cascades;
+            self.bind_group = bind_group;
+        }
+        self.settings = settings;
+    }
+
+    /// Fits the cascades to `camera`. `caster` is the index of the shadow
+    /// casting light in the light uniform and the direction it shines in,
+    /// or `None` to turn shadows off.
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera, caster: Option<(u32, Vector3<f32>)>) {
+        let mut uniform = ShadowUniform {
+            pcf_radius: self.settings.pcf_radius,
+            ..bytemuck::Zeroable::zeroed()
+        };
+        let cascades = match caster {
+            Some((light_index, direction)) => {
+                uniform.light_index = light_index;
+                fit_cascades(camera, direction, &self.settings)
+            }
+            None => Vec::new(),
+        };
+        for (i, (cascade, target)) in cascades.iter().zip(&self.cascades).enumerate() {
+            uniform.view_proj[i] = cascade.view_proj.into();
+            uniform.depth_bias[i] = self.settings.depth_bias / cascade.depth_range;
+            uniform.normal_offset[i] = self.settings.normal_bias * cascade.texel_size;
+            let view_proj: [[f32; 4]; 4] = cascade.view_proj.into();
+            queue.write_buffer(&target.buffer, 0, bytemuck::cast_slice(&view_proj));
+        }
+        uniform.cascade_count = cascades.len() as u32;
+        self.active_cascades = cascades.len();
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    /// The depth view to render each cascade into, with the bind group
+    /// holding its projection (group 0 of `pipeline`). Empty while no light
+    /// casts shadows.
+    pub fn cascades(&self) -> impl Iterator<Item = (&wgpu::TextureView, &wgpu::BindGroup)> {
+        self.cascades[..self.active_cascades]
+            .iter()
+            .map(|cascade| (&cascade.view, &cascade.bind_group))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{point3, vec3, Vector4};
+
+    use super::*;
+    use crate::camera::{Projection, Viewport};
+
+    #[test]
+    fn shadow_maps_fit_the_device_and_the_cascades() {
+        let context = pollster::block_on(Context::new_headless()).unwrap();
+        let settings = ShadowSettings {
+            resolution: 0,
+            cascades: 1,
+            ..ShadowSettings::default()
+        };
+        let mut shadows = pollster::block_on(ShadowMaps::new(&context, settings)).unwrap();
+        assert_eq!(shadows.settings().resolution, 1);
+        assert_eq!(shadows.cascades.len() as u32, shadows.min_layers);
+
+        let settings = ShadowSettings {
+            resolution: 16,
+            cascades: 3,
+            ..ShadowSettings::default()
+        };
+        shadows.set_settings(&context.device, settings);
+        assert_eq!(shadows.cascades.len(), 3);
+
+        let settings = ShadowSettings {
+            resolution: u32::MAX,
+            ..ShadowSettings::default()
+        };
+        let max = context.device.limits().max_texture_dimension_2d;
+        assert_eq!(clamp_resolution(&context.device, settings).resolution, max);
+    }
+
+    #[test]
+    fn splits_cover_the_range_in_order() {
+        let splits = cascade_splits(0.1, 100.0, 4, 0.75);
+        assert_eq!(splits.len(), 5);
+        assert!((splits[0] - 0.1).abs() < 1e-6);
+        assert!((splits[4] - 100.0).abs() < 1e-3);
+        assert!(splits.windows(2).all(|split| split[0] < split[1]));
+    }
+
+    #[test]
+    fn cascades_contain_their_frustum_slice() {
+        let camera = Camera {
+            eye: point3(0.0, 8.0, 10.0),
+            up: vec3(0.0, 1.0, 0.0),
+            target: point3(0.0, 0.0, 0.0),
+            projection: Projection::Perspective { fov: 45.0 },
+            viewport: Viewport::new(800, 600, 1.0),
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        let settings = ShadowSettings::default();
+        let direction = vec3(-1.0, -2.0, -0.5);
+        let cascades = fit_cascades(&camera, direction, &settings);
+        let splits = cascade_splits(0.1, settings.max_distance, cascades.len(), settings.split_lambda);
+        for (cascade, split) in cascades.iter().zip(splits.windows(2)) {
+            for corner in camera.frustum_corners(split[0], split[1]) {
+                let clip = cascade.view_proj * Vector4::new(corner.x, corner.y, corner.z, 1.0);
+                assert!(clip.x.abs() <= 1.0 && clip.y.abs() <= 1.0, "{:?} is outside", corner);
+                assert!((0.0..=1.0).contains(&clip.z), "{:?} is outside", corner);
+            }
+        }
+    }
+}
@@ -0,0 +1,408 @@
+//! Procedurally generated meshes.
+//!
+//! Every generator returns `MeshData` centered on the origin, with front
+//! faces wound counter-clockwise as seen from outside, unit normals and
+//! tangents pointing along +u. Texture coordinates follow wgpu, with v
+//! growing downwards.
+
+use std::{collections::HashMap, f32::consts::PI};
+
+use cgmath::{vec2, vec3, InnerSpace, Vector2, Vector3};
+
+use crate::{
+    mesh::{self, Mesh},
+    vertex::Vertex,
+};
+
+/// Geometry on the CPU, before it is uploaded as a `Mesh`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeshData {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    pub fn upload(&self, device: &wgpu::Device, name: &str) -> Mesh {
+        Mesh::new(device, name, &self.vertices, &self.indices)
+    }
+
+    fn push_vertex(&mut self, position: Vector3<f32>, uv: Vector2<f32>, normal: Vector3<f32>) -> u32 {
+        self.vertices.push(Vertex {
+            position: position.into(),
+            uv: uv.into(),
+            normal: normal.normalize().into(),
+            tangent: [0.0; 4],
+        });
+        self.vertices.len() as u32 - 1
+    }
+
+    /// Adds a grid of `columns` x `rows` quads facing `right.cross(up)`,
+    /// centered on `center` and spanning `right` and `up` in each direction.
+    fn push_grid(
+        &mut self,
+        center: Vector3<f32>,
+        right: Vector3<f32>,
+        up: Vector3<f32>,
+        columns: u32,
+        rows: u32,
+    ) {
+        let normal = right.cross(up);
+        let first = self.vertices.len() as u32;
+        for row in 0..=rows {
+            let v = row as f32 / rows as f32;
+            for column in 0..=columns {
+                let u = column as f32 / columns as f32;
+                let position = center + right * (u * 2.0 - 1.0) + up * (1.0 - v * 2.0);
+                self.push_vertex(position, vec2(u, v), normal);
+            }
+        }
+        for row in 0..rows {
+            for column in 0..columns {
+                let top_left = first + row * (columns + 1) + column;
+                let bottom_left = top_left + columns + 1;
+                self.indices.extend([top_left, bottom_left, bottom_left + 1]);
+                self.indices.extend([top_left, bottom_left + 1, top_left + 1]);
+            }
+        }
+    }
+
+    /// Sweeps `profile` around the y axis with `segments` steps.
+    ///
+    /// Rows with a zero radius (poles and apexes) get their triangles
+    /// collapsed instead of degenerate ones, and their vertices sit halfway
+    /// between the segments, so their normals and u match the triangles
+    /// they belong to.
+    fn push_revolution(&mut self, profile: &[ProfilePoint], segments: u32) {
+        let first = self.vertices.len() as u32;
+        for point in profile {
+            let offset = if point.radius == 0.0 { 0.5 } else { 0.0 };
+            for segment in 0..=segments {
+                let u = (segment as f32 + offset) / segments as f32;
+                let (sin, cos) = (u * 2.0 * PI).sin_cos();
+                // u = 0 starts at +z and turns towards +x, so u grows to the
+                // right when looking at the surface from outside
+                let position = vec3(point.radius * sin, point.y, point.radius * cos);
+                let normal = vec3(point.normal.x * sin, point.normal.y, point.normal.x * cos);
+                self.push_vertex(position, vec2(u, point.v), normal);
+            }
+        }
+        for (row, pair) in profile.windows(2).enumerate() {
+            for segment in 0..segments {
+                let top_left = first + row as u32 * (segments + 1) + segment;
+                let bottom_left = top_left + segments + 1;
+                if pair[1].radius != 0.0 {
+                    self.indices.extend([top_left, bottom_left, bottom_left + 1]);
+                }
+                if pair[0].radius != 0.0 {
+                    self.indices.extend([top_left, bottom_left + 1, top_left + 1]);
+                }
+            }
+        }
+    }
+
+    /// Adds a flat disc at height `y`, facing up or down.
+    fn push_disc(&mut self, y: f32, radius: f32, segments: u32, facing_up: bool) {
+        let normal = vec3(0.0, if facing_up { 1.0 } else { -1.0 }, 0.0);
+        // Seen from the side the disc faces, x points right and z down
+        // (facing up) or up (facing down)
+        let v_sign = if facing_up { 1.0 } else { -1.0 };
+        let center = self.push_vertex(vec3(0.0, y, 0.0), vec2(0.5, 0.5), normal);
+        for segment in 0..=segments {
+            let (sin, cos) = (segment as f32 / segments as f32 * 2.0 * PI).sin_cos();
+            let uv = vec2(0.5 + sin * 0.5, 0.5 + cos * 0.5 * v_sign);
+            self.push_vertex(vec3(radius * sin, y, radius * cos), uv, normal);
+        }
+        for segment in 0..segments {
+            let current = center + 1 + segment;
+            if facing_up {
+                self.indices.extend([center, current, current + 1]);
+            } else {
+                self.indices.extend([center, current + 1, current]);
+            }
+        }
+    }
+
+    fn generate_tangents(mut self) -> Self {
+        mesh::generate_tangents(&mut self.vertices, &self.indices);
+        self
+    }
+}
+
+/// A point of the outline swept by `MeshData::push_revolution`.
+struct ProfilePoint {
+    /// Distance from the y axis
+    radius: f32,
+    y: f32,
+    /// Normal in the (radius, y) plane
+    normal: Vector2<f32>,
+    v: f32,
+}
+
+/// A cube with sides of length `size`. Every face has its own vertices and
+/// the full texture.
+pub fn cube(size: f32) -> MeshData {
+    let h = size / 2.0;
+    let (x, y, z) = (Vector3::unit_x() * h, Vector3::unit_y() * h, Vector3::unit_z() * h);
+    let mut mesh = MeshData::default();
+    // (right, up) for every face, with right x up pointing outwards
+    for (right, up) in [(x, y), (-x, y), (-z, y), (z, y), (x, -z), (x, z)] {
+        let center = right.cross(up) / h;
+        mesh.push_grid(center, right, up, 1, 1);
+    }
+    mesh.generate_tangents()
+}
+
+/// A flat `width` x `depth` plane on the xz plane facing up, split into
+/// `columns` x `rows` quads.
+pub fn plane(width: f32, depth: f32, columns: u32, rows: u32) -> MeshData {
+    let mut mesh = MeshData::default();
+    let right = Vector3::unit_x() * (width / 2.0);
+    // The top of the texture is at -z
+    let up = -Vector3::unit_z() * (depth / 2.0);
+    mesh.push_grid(vec3(0.0, 0.0, 0.0), right, up, columns.max(1), rows.max(1));
+    mesh.generate_tangents()
+}
+
+/// A sphere made of `segments` slices around the y axis and `rings` rings
+/// from pole to pole, with the texture wrapped around it once.
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> MeshData {
+    let rings = rings.max(2);
+    let profile = (0..=rings)
+        .map(|ring| {
+            let v = ring as f32 / rings as f32;
+            let (sin, cos) = (v * PI).sin_cos();
+            ProfilePoint {
+                radius: if ring == 0 || ring == rings { 0.0 } else { radius * sin },
+                y: radius * cos,
+                normal: vec2(sin, cos),
+                v,
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut mesh = MeshData::default();
+    mesh.push_revolution(&profile, segments.max(3));
+    mesh.generate_tangents()
+}
+
+/// A sphere made by splitting every triangle of an icosahedron into four
+/// `subdivisions` times, for evenly sized triangles. Texture coordinates
+/// match `uv_sphere`.
+pub fn ico_sphere(radius: f32, subdivisions: u32) -> MeshData {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut positions = [
+        (-1.0, t, 0.0),
+        (1.0, t, 0.0),
+        (-1.0, -t, 0.0),
+        (1.0, -t, 0.0),
+        (0.0, -1.0, t),
+        (0.0, 1.0, t),
+        (0.0, -1.0, -t),
+        (0.0, 1.0, -t),
+        (t, 0.0, -1.0),
+        (t, 0.0, 1.0),
+        (-t, 0.0, -1.0),
+        (-t, 0.0, 1.0),
+    ]
+    .map(|(x, y, z)| vec3(x, y, z).normalize())
+    .to_vec();
+    let mut triangles = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let position = (positions[a as usize] + positions[b as usize]).normalize();
+                positions.push(position);
+                positions.len() as u32 - 1
+            })
+        };
+        triangles = triangles
+            .into_iter()
+            .flat_map(|[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    // Vertices are shared between triangles unless they need different
+    // texture coordinates, along the seam and at the poles
+    let mut mesh = MeshData::default();
+    let mut vertices = HashMap::new();
+    for triangle in triangles {
+        let mut uvs = triangle.map(|i| {
+            let p = positions[i as usize];
+            vec2((p.x.atan2(p.z) / (2.0 * PI)).rem_euclid(1.0), p.y.clamp(-1.0, 1.0).acos() / PI)
+        });
+        // Triangles crossing the seam at u = 0 get u > 1 on one side, so the
+        // texture repeats instead of running backwards across the triangle
+        let (min_u, max_u) = uvs.iter().fold((1.0f32, 0.0f32), |(min, max), uv| (min.min(uv.x), max.max(uv.x)));
+        if max_u - min_u > 0.5 {
+            for uv in &mut uvs {
+                if uv.x < 0.5 {
+                    uv.x += 1.0;
+                }
+            }
+        }
+        // u is meaningless at the poles, use the one of the opposite edge
+        for corner in 0..3 {
+            let p = positions[triangle[corner] as usize];
+            if 1.0 - p.y.abs() < 1e-6 {
+                uvs[corner].x = (uvs[(corner + 1) % 3].x + uvs[(corner + 2) % 3].x) / 2.0;
+            }
+        }
+        for (corner, uv) in triangle.into_iter().zip(uvs) {
+            let key = (corner, uv.x.to_bits(), uv.y.to_bits());
+            let index = *vertices.entry(key).or_insert_with(|| {
+                let normal = positions[corner as usize];
+                mesh.push_vertex(normal * radius, uv, normal)
+            });
+            mesh.indices.push(index);
+        }
+    }
+    mesh.generate_tangents()
+}
+
+/// A cylinder along the y axis with flat caps.
+pub fn cylinder(radius: f32, height: f32, segments: u32) -> MeshData {
+    let segments = segments.max(3);
+    let h = height / 2.0;
+    let mut mesh = MeshData::default();
+    mesh.push_revolution(
+        &[
+            ProfilePoint { radius, y: h, normal: vec2(1.0, 0.0), v: 0.0 },
+            ProfilePoint { radius, y: -h, normal: vec2(1.0, 0.0), v: 1.0 },
+        ],
+        segments,
+    );
+    mesh.push_disc(h, radius, segments, true);
+    mesh.push_disc(-h, radius, segments, false);
+    mesh.generate_tangents()
+}
+
+/// A cone along the y axis with its apex at the top and a flat base.
+pub fn cone(radius: f32, height: f32, segments: u32) -> MeshData {
+    let segments = segments.max(3);
+    let h = height / 2.0;
+    // Perpendicular to the slanted side
+    let normal = vec2(height, radius).normalize();
+    let mut mesh = MeshData::default();
+    mesh.push_revolution(
+        &[
+            ProfilePoint { radius: 0.0, y: h, normal, v: 0.0 },
+            ProfilePoint { radius, y: -h, normal, v: 1.0 },
+        ],
+        segments,
+    );
+    mesh.push_disc(-h, radius, segments, false);
+    mesh.generate_tangents()
+}
+
+/// A torus around the y axis. `major_radius` is the distance from the
+/// center to the middle of the tube and `minor_radius` the tube's radius.
+pub fn torus(major_radius: f32, minor_radius: f32, segments: u32, tube_segments: u32) -> MeshData {
+    let tube_segments = tube_segments.max(3);
+    let profile = (0..=tube_segments)
+        .map(|i| {
+            let v = i as f32 / tube_segments as f32;
+            // Starts on the outside and goes down first, so v grows
+            // downwards when looking at the outside of the tube
+            let (sin, cos) = (v * 2.0 * PI).sin_cos();
+            ProfilePoint {
+                radius: major_radius + minor_radius * cos,
+                y: -minor_radius * sin,
+                normal: vec2(cos, -sin),
+                v,
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut mesh = MeshData::default();
+    mesh.push_revolution(&profile, segments.max(3));
+    mesh.generate_tangents()
+}
+
+/// A cylinder of `height` with hemispheres of `radius` on both ends, so
+/// its total height is `height + 2 * radius`. Each hemisphere has `rings`
+/// rings and v is spread evenly along the outline.
+pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> MeshData {
+    let rings = rings.max(1);
+    let h = height / 2.0;
+    let length = PI * radius + height;
+    let hemisphere = |top: bool| {
+        (0..=rings).map(move |ring| {
+            let step = ring as f32 / rings as f32;
+            // Angle from the top pole
+            let angle = if top { step } else { 1.0 + step } * PI / 2.0;
+            let (sin, cos) = angle.sin_cos();
+            let arc = angle * radius + if top { 0.0 } else { height };
+            let pole = (top && ring == 0) || (!top && ring == rings);
+            ProfilePoint {
+                radius: if pole { 0.0 } else { radius * sin },
+                y: radius * cos + if top { h } else { -h },
+                normal: vec2(sin, cos),
+                v: arc / length,
+            }
+        })
+    };
+    let profile = hemisphere(true).chain(hemisphere(false)).collect::<Vec<_>>();
+    let mut mesh = MeshData::default();
+    mesh.push_revolution(&profile, segments.max(3));
+    mesh.generate_tangents()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_shapes() -> Vec<(&'static str, MeshData)> {
+        vec![
+            ("cube", cube(1.0)),
+            ("plane", plane(2.0, 1.0, 4, 3)),
+            ("uv_sphere", uv_sphere(1.0, 16, 8)),
+            ("ico_sphere", ico_sphere(1.0, 2)),
+            ("cylinder", cylinder(0.5, 2.0, 12)),
+            ("cone", cone(0.5, 1.0, 12)),
+            ("torus", torus(1.0, 0.25, 16, 8)),
+            ("capsule", capsule(0.5, 1.0, 12, 4)),
+        ]
+    }
+
+    #[test]
+    fn shapes_face_outwards_with_unit_normals_and_tangents() {
+        for (name, mesh) in all_shapes() {
+            assert_eq!(mesh.indices.len() % 3, 0, "{}", name);
+            for vertex in &mesh.vertices {
+                let normal = Vector3::from(vertex.normal);
+                let tangent = vec3(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+                assert!((normal.magnitude() - 1.0).abs() < 1e-4, "{}", name);
+                assert!((tangent.magnitude() - 1.0).abs() < 1e-4, "{}", name);
+                assert!(normal.dot(tangent).abs() < 1e-4, "{}", name);
+                assert!(vertex.tangent[3].abs() == 1.0, "{}", name);
+            }
+            for triangle in mesh.indices.chunks_exact(3) {
+                let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[triangle[i] as usize]);
+                let face = (Vector3::from(b.position) - Vector3::from(a.position))
+                    .cross(Vector3::from(c.position) - Vector3::from(a.position));
+                assert!(face.magnitude() > 1e-6, "{} has a degenerate triangle", name);
+                let normals = Vector3::from(a.normal) + Vector3::from(b.normal) + Vector3::from(c.normal);
+                assert!(face.dot(normals) > 0.0, "{} has a triangle wound inwards", name);
+            }
+        }
+    }
+
+    #[test]
+    fn sphere_vertices_lie_on_the_surface() {
+        for mesh in [uv_sphere(2.0, 12, 6), ico_sphere(2.0, 3)] {
+            for vertex in &mesh.vertices {
+                let position = Vector3::from(vertex.position);
+                assert!((position.magnitude() - 2.0).abs() < 1e-4);
+                assert!((position / 2.0 - Vector3::from(vertex.normal)).magnitude() < 1e-4);
+            }
+        }
+    }
+}
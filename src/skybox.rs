@@ -0,0 +1,166 @@
+use cgmath::SquareMatrix;
+use wgpu::util::DeviceExt;
+
+use crate::{camera::Camera, context::Context, error::Result, texture::Texture};
+
+/// A cube texture drawn behind all geometry, following the rotation of the
+/// camera but not its position.
+///
+/// It is drawn after the opaque geometry, on the far plane, so it only
+/// covers the pixels nothing else was drawn to.
+pub struct Skybox {
+    /// Viewed with `TextureViewDimension::Cube`, e.g. from
+    /// `Texture::from_cube_faces`
+    texture: Texture,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Skybox {
+    pub async fn new(context: &Context, texture: Texture, format: wgpu::TextureFormat) -> Result<Self> {
+        let device = &context.device;
+        let matrix: [[f32; 4]; 4] = cgmath::Matrix4::identity().into();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skybox Buffer"),
+            contents: bytemuck::cast_slice(&matrix),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("skybox_bind_group_layout"),
+        });
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &buffer, &texture);
+
+        let shader = context
+            .create_shader_module("Skybox Shader", include_str!("skybox.wgsl"))
+            .await?;
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox Pipeline Layout"),
+            push_constant_ranges: &[],
+            bind_group_layouts: &[&bind_group_layout],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Skybox Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vertex_main",
+                // The vertices are generated in the shader
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fragment_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                // The sky is exactly on the far plane, where the depth
+                // buffer was cleared to
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Ok(Self {
+            texture,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        texture: &Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("skybox_bind_group"),
+        })
+    }
+
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// Replaces the cube texture, see `Texture::from_cube_faces` and
+    /// `Texture::from_equirectangular`.
+    pub fn set_texture(&mut self, device: &wgpu::Device, texture: Texture) {
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.buffer, &texture);
+        self.texture = texture;
+    }
+
+    /// Turns the sky with `camera`.
+    pub fn update(&self, queue: &wgpu::Queue, camera: &Camera) {
+        let clip_to_world = camera
+            .build_rotation_view_proj()
+            .invert()
+            .unwrap_or_else(cgmath::Matrix4::identity);
+        let matrix: [[f32; 4]; 4] = clip_to_world.into();
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&matrix));
+    }
+
+    /// Draws the sky into a pass whose depth attachment already holds the
+    /// opaque geometry.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
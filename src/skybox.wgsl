@@ -0,0 +1,36 @@
+// Draws the sky behind everything else, see skybox.rs
+
+struct Sky {
+    // Inverse of the camera's view projection without the eye position
+    clip_to_world: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> sky: Sky;
+@group(0) @binding(1)
+var sky_texture: texture_cube<f32>;
+@group(0) @binding(2)
+var sky_sampler: sampler;
+
+struct VOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) clip: vec2<f32>,
+}
+
+@vertex
+fn vertex_main(@builtin(vertex_index) index: u32) -> VOutput {
+    // A single triangle covering the whole screen, on the far plane so
+    // all geometry is in front of it
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VOutput;
+    out.clip = uv * 2.0 - 1.0;
+    out.position = vec4<f32>(out.clip, 1.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fragment_main(input: VOutput) -> @location(0) vec4<f32> {
+    let far = sky.clip_to_world * vec4<f32>(input.clip, 1.0, 1.0);
+    let direction = far.xyz / far.w;
+    // Cube textures are left-handed, see Texture::from_cube_faces
+    return textureSample(sky_texture, sky_sampler, vec3<f32>(direction.xy, -direction.z));
+}
@@ -1,94 +1,130 @@
-use cgmath::{SquareMatrix, vec3, point3};
-use image::GenericImageView;
+use std::collections::HashMap;
+
+use cgmath::{vec3, point3};
 use wgpu::util::DeviceExt;
 use winit::{
-    event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event::{DeviceEvent, ElementState, KeyboardInput, VirtualKeyCode, WindowEvent},
     window::Window,
 };
 
 use crate::{
-    camera::{Camera, CameraUniform, OPENGL_TO_WGPU_MATRIX},
-    controller::CameraController,
+    assets::{Assets, Handle, Reloaded},
+    camera::{Camera, CameraUniform, OrthographicSize, Projection, Viewport},
+    context::Context,
+    controller::{CameraController, Controller},
+    error::Result,
+    headless::{OffscreenTarget, OFFSCREEN_FORMAT},
+    light::{Light, LightId, Lights},
+    mesh::Mesh,
+    model::Model,
+    pbr::PbrMaterial,
+    scene::Scene,
+    shadow::{ShadowMaps, ShadowSettings},
+    skybox::Skybox,
     texture,
-    vertex::{TextureLoad, Vertex, SQUARE_INDICES, SQUARE_VERTICES}, instance::{Instance, InstanceRaw},
+    time::FrameTime,
+    vertex::{TextureLoad, Vertex, VertexLayout, DIRT_LAYER, SQUARE_INDICES, SQUARE_VERTICES, TREE_LAYER}, instance::{Instance, InstanceRaw},
 };
 
+/// Where the frames produced by `State::render` end up.
+enum RenderTarget {
+    Surface(wgpu::Surface),
+    Offscreen(OffscreenTarget),
+}
+
 pub struct State {
-    surface: wgpu::Surface,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
+    target: RenderTarget,
+    context: Context,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
-    clear_color: wgpu::Color,
+    /// Drawn behind everything, in place of a clear color
+    skybox: Skybox,
     render_pipeline: wgpu::RenderPipeline,
-    square_vertex_buffer: wgpu::Buffer,
-    square_index_buffer: wgpu::Buffer,
-    tree_bind_group: wgpu::BindGroup,
-    tree_texture: texture::Texture,
-    dirt_bind_group: wgpu::BindGroup,
-    dirt_texture: texture::Texture,
+    /// Like `render_pipeline`, but with the texture array, see
+    /// `TextureLoad::Array`
+    array_pipeline: wgpu::RenderPipeline,
+    /// Draws meshes whose material is a `PbrMaterial`, with the faces of
+    /// double sided materials and of mirrored scene objects
+    pbr_pipelines: HashMap<Faces, wgpu::RenderPipeline>,
+    pbr_material_layout: wgpu::BindGroupLayout,
+    mesh: Mesh,
+    /// Drawn instead of `mesh` when set
+    model: Option<Handle<Model>>,
+    /// Drawn in addition to the instances, with their own instance buffers
+    scene: Option<Handle<Scene>>,
+    assets: Assets,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    texture_array_layout: wgpu::BindGroupLayout,
+    /// The tree and dirt textures, at `TREE_LAYER` and `DIRT_LAYER`
+    array_texture: Handle<texture::Texture>,
+    array_bind_group: wgpu::BindGroup,
+    /// The bind group of every texture selectable with `TextureLoad::Custom`
+    texture_bind_groups: HashMap<Handle<texture::Texture>, wgpu::BindGroup>,
     texture_load: TextureLoad,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
     camera_uniform: CameraUniform,
     camera: Camera,
-    controller: CameraController,
+    /// The camera before the last `update`, to interpolate from
+    previous_camera: Camera,
+    controller: Box<dyn Controller>,
+    lights: Lights,
+    shadows: ShadowMaps,
     instances: Vec<Instance>,
     instance_buffer: wgpu::Buffer,
+    depth_texture: texture::Texture,
 }
 
 impl State {
-    pub async fn new(window: &Window) -> Self {
+    pub async fn new(window: &Window) -> Result<Self> {
         let size = window.inner_size();
-        let instace = wgpu::Instance::new(wgpu::Backends::all());
-        let surface = unsafe { instace.create_surface(&window) };
-        // Handle to the graphics card
-        let adapter = instace
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::default(),
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        let (context, surface) = Context::new(window).await?;
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: surface.get_supported_formats(&context.adapter)[0],
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+        };
+        surface.configure(&context.device, &config);
+
+        let target = RenderTarget::Surface(surface);
+        Self::with_target(context, target, config, window.scale_factor()).await
+    }
+
+    /// Creates a state that renders into an offscreen texture instead of a
+    /// window, so it can run without a display (e.g. in CI).
+    pub async fn new_headless(size: winit::dpi::PhysicalSize<u32>) -> Result<Self> {
+        let context = Context::new_headless().await?;
 
+        // Not used to configure anything, but it keeps the size and format
+        // in one place for both kinds of targets
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface.get_supported_formats(&adapter)[0],
+            format: OFFSCREEN_FORMAT,
             width: size.width,
             height: size.height,
             present_mode: wgpu::PresentMode::Fifo,
             alpha_mode: wgpu::CompositeAlphaMode::Auto,
         };
-        surface.configure(&device, &config);
-
-        // Load image from asset
-        let tree_texture = texture::Texture::from_bytes(
-            &device,
-            &queue,
-            include_bytes!("../assets/tree.png"),
-            "tree.png",
-        )
-        .unwrap();
-        let dirt_texture = texture::Texture::from_bytes(
-            &device,
-            &queue,
-            include_bytes!("../assets/dirt.png"),
-            "dirt.png",
-        )
-        .unwrap();
+        let target = RenderTarget::Offscreen(OffscreenTarget::new(&context.device, size));
+
+        Self::with_target(context, target, config, 1.0).await
+    }
+
+    async fn with_target(
+        context: Context,
+        target: RenderTarget,
+        config: wgpu::SurfaceConfiguration,
+        scale_factor: f64,
+    ) -> Result<Self> {
+        let size = winit::dpi::PhysicalSize::new(config.width, config.height);
+        let Context { device, queue, .. } = &context;
+
+        let mut assets = Assets::new();
+        let array_texture = load_texture_array(&mut assets, &context)?;
 
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -114,49 +150,46 @@ impl State {
                 ],
                 label: Some("texture_bind_group_layout"),
             });
-
-        let tree_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
+        let texture_array_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[
-                wgpu::BindGroupEntry {
+                wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&tree_texture.view),
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
                 },
-                wgpu::BindGroupEntry {
+                wgpu::BindGroupLayoutEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&tree_texture.sampler),
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
                 },
             ],
-            label: Some("tree_bind_group"),
+            label: Some("texture_array_layout"),
         });
 
-        let dirt_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&dirt_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&dirt_texture.sampler),
-                },
-            ],
-            label: Some("dirt_bind_group"),
-        });
+        let array_bind_group = assets.get(array_texture).unwrap().create_bind_group(
+            device,
+            &texture_array_layout,
+            "array_bind_group",
+        );
         let camera = Camera {
             eye: point3(0.0, 0.0, 5.0),
             up: vec3(0.0, 1.0, 0.0),
             target: point3(0.0, 0.0, -1.0),
-            fov: 45.0,
-            ratio: size.width as f32 / size.height as f32,
+            projection: Projection::Perspective { fov: 45.0 },
+            viewport: Viewport::new(size.width, size.height, scale_factor),
             znear: 0.1,
             zfar: 100.0,
         };
         let mut camera_uniform = CameraUniform::new();
-        camera_uniform.update_view_proj(&camera, &window);
+        camera_uniform.update_view_proj(&camera);
 
-        let controller = CameraController::new(0.01);
+        let controller: Box<dyn Controller> = Box::new(CameraController::new(2.0));
 
         let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Camera Buffer"),
@@ -168,7 +201,8 @@ impl State {
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
+                    // The fragment shader needs the eye position for lighting
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -188,119 +222,129 @@ impl State {
             label: Some("camera_bind_group"),
         });
 
-        let clear_color = wgpu::Color::BLACK;
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-        });
+        let lights = Lights::new(device);
+        let shadows = ShadowMaps::new(&context, ShadowSettings::default()).await?;
+
+        let depth_texture =
+            texture::Texture::create_depth_texture(device, &config, "depth_texture");
+
+        // Black until a sky is set, like the clear color used to be
+        let sky_texture = texture::Texture::cube_from_color(device, queue, [0, 0, 0, 255], "Sky")?;
+        let skybox = Skybox::new(&context, sky_texture, config.format).await?;
+        let shader = context
+            .create_shader_module("Shader", include_str!("shader.wgsl"))
+            .await?;
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
                 push_constant_ranges: &[],
-                bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
+                bind_group_layouts: &[
+                    &texture_bind_group_layout,
+                    &camera_bind_group_layout,
+                    &lights.bind_group_layout,
+                    &shadows.bind_group_layout,
+                ],
             });
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vertex_main",
-                buffers: &[Vertex::desc(), InstanceRaw::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fragment_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
+        let render_pipeline = create_render_pipeline(
+            device,
+            "Render Pipeline",
+            &render_pipeline_layout,
+            &shader,
+            "fragment_main",
+            config.format,
+            ONE_SIDED,
+        );
+        let array_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Array Pipeline Layout"),
+            push_constant_ranges: &[],
+            bind_group_layouts: &[
+                &texture_array_layout,
+                &camera_bind_group_layout,
+                &lights.bind_group_layout,
+                &shadows.bind_group_layout,
+            ],
         });
+        let array_pipeline = create_render_pipeline(
+            device,
+            "Array Pipeline",
+            &array_pipeline_layout,
+            &shader,
+            "fragment_array",
+            config.format,
+            ONE_SIDED,
+        );
 
-        let square_vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Square Vertex Buffer"),
-            contents: bytemuck::cast_slice(SQUARE_VERTICES),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-        let square_index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Square Index Buffer"),
-            contents: bytemuck::cast_slice(SQUARE_INDICES),
-            usage: wgpu::BufferUsages::INDEX,
+        let pbr_material_layout = PbrMaterial::bind_group_layout(device);
+        let pbr_shader = context
+            .create_shader_module("PBR Shader", include_str!("pbr.wgsl"))
+            .await?;
+        let pbr_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PBR Pipeline Layout"),
+            push_constant_ranges: &[],
+            bind_group_layouts: &[
+                &pbr_material_layout,
+                &camera_bind_group_layout,
+                &lights.bind_group_layout,
+                &shadows.bind_group_layout,
+            ],
         });
-        let texture_load = TextureLoad::TREE;
-        const INSTANCES_PER_ROW: u32 = 10;
-        use cgmath::prelude::*;
-        const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(INSTANCES_PER_ROW as f32 * 0.7, 0.0, INSTANCES_PER_ROW as f32 * 0.5);
-        let instances = (0..INSTANCES_PER_ROW).flat_map(|z| {
-            (0..INSTANCES_PER_ROW).map(move |x| {
-                let pos = cgmath::Vector3 { x: x as f32, y: 0.0, z: z as f32 } - INSTANCE_DISPLACEMENT;
-
-                let rot = if pos.is_zero() {
-                    // this is needed so an object at (0, 0, 0) won't get scaled to zero
-                    // as Quaternions can effect scale if they're not created correctly
-                    cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(0.0))
-                } else {
-                    cgmath::Quaternion::from_axis_angle(pos.normalize(), cgmath::Deg(45.0))
-                };
-
-                Instance {
-                    pos,
-                    rot,
-                }
+        // Double sided materials aren't culled, and mirrored objects are
+        // wound the other way around
+        let pbr_pipelines = [Some(wgpu::Face::Back), None]
+            .into_iter()
+            .flat_map(|cull_mode| {
+                [wgpu::FrontFace::Ccw, wgpu::FrontFace::Cw].map(|front_face| (cull_mode, front_face))
             })
-        }).collect::<Vec<_>>();
-
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Instance Buffer"),
-                contents: bytemuck::cast_slice(&instance_data),
-                usage: wgpu::BufferUsages::VERTEX,
-            }
-        );     
-        Self {
-            surface,
-            device,
-            queue,
+            .map(|faces| {
+                let pipeline = create_render_pipeline(
+                    device,
+                    "PBR Pipeline",
+                    &pbr_pipeline_layout,
+                    &pbr_shader,
+                    "fragment_main",
+                    config.format,
+                    faces,
+                );
+                (faces, pipeline)
+            })
+            .collect();
+
+        let mesh = Mesh::new(device, "Square", SQUARE_VERTICES, SQUARE_INDICES);
+        let texture_load = TextureLoad::Array;
+        let instances = Vec::new();
+        let instance_buffer = InstanceRaw::create_buffer(device, &instances);
+        Ok(Self {
+            target,
+            context,
             config,
             size,
-            clear_color,
+            skybox,
             render_pipeline,
-            square_vertex_buffer,
-            square_index_buffer,
-            tree_bind_group,
-            dirt_bind_group,
+            array_pipeline,
+            pbr_pipelines,
+            pbr_material_layout,
+            mesh,
+            model: None,
+            scene: None,
+            assets,
+            texture_bind_group_layout,
+            texture_array_layout,
+            array_texture,
+            array_bind_group,
+            texture_bind_groups: HashMap::new(),
             texture_load,
-            tree_texture,
-            dirt_texture,
             camera_bind_group,
             camera_buffer,
             camera_uniform,
             camera,
+            previous_camera: camera,
             controller,
+            lights,
+            shadows,
             instances,
             instance_buffer,
-        }
+            depth_texture,
+        })
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -308,23 +352,29 @@ impl State {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            match &mut self.target {
+                RenderTarget::Surface(surface) => surface.configure(&self.context.device, &self.config),
+                RenderTarget::Offscreen(offscreen) => {
+                    *offscreen = OffscreenTarget::new(&self.context.device, new_size)
+                }
+            }
+            self.depth_texture =
+                texture::Texture::create_depth_texture(&self.context.device, &self.config, "depth_texture");
+
+            self.camera.viewport.width = new_size.width;
+            self.camera.viewport.height = new_size.height;
+            self.upload_camera();
         }
     }
 
+    /// Handles the window moving to a monitor with a different DPI, along
+    /// with the new physical size the window got for it.
+    pub fn rescale(&mut self, scale_factor: f64, new_size: winit::dpi::PhysicalSize<u32>) {
+        self.camera.viewport.scale_factor = scale_factor;
+        self.resize(new_size);
+    }
+
     pub fn input(&mut self, event: &WindowEvent) -> bool {
-        if let WindowEvent::CursorMoved { position, .. } = event {
-            println!("Capturing mouse events");
-            println!("Red {}", position.x as f64 / self.size.width as f64);
-            println!("Green {}", position.y as f64 / self.size.height as f64);
-            self.clear_color = wgpu::Color {
-                r: position.x as f64 / self.size.width as f64,
-                g: position.y as f64 / self.size.height as f64,
-                b: 1.0,
-                a: 1.0,
-            };
-            return true;
-        }
         if let WindowEvent::KeyboardInput {
             input:
                 KeyboardInput {
@@ -335,79 +385,679 @@ impl State {
             ..
         } = event
         {
-            self.texture_load = match self.texture_load {
-                TextureLoad::TREE => TextureLoad::DIRT,
-                TextureLoad::DIRT => TextureLoad::TREE,
-            };
+            // Swaps the tree and dirt instances
+            let instances = self
+                .instances
+                .iter()
+                .map(|instance| Instance {
+                    texture_layer: match instance.texture_layer {
+                        TREE_LAYER => DIRT_LAYER,
+                        _ => TREE_LAYER,
+                    },
+                    ..*instance
+                })
+                .collect();
+            self.set_instances(instances);
+        }
+        if let WindowEvent::KeyboardInput {
+            input:
+                KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::P),
+                    ..
+                },
+            ..
+        } = event
+        {
+            self.set_projection(match self.camera.projection {
+                Projection::Perspective { .. } => {
+                    Projection::Orthographic(OrthographicSize::Height(10.0))
+                }
+                Projection::Orthographic(_) => Projection::Perspective { fov: 45.0 },
+            });
         }
         self.controller.process_events(event);
         false
     }
 
-    pub fn update(&mut self, window: &Window) {
-        let w_aspect_ratio = (self.get_size().width as f32) / 100.0;
-        let h_aspect_ratio = (self.get_size().height as f32) / 100.0;
-        self.controller.update_camera(&mut self.camera);
-        self.camera_uniform.update_view_proj(&self.camera, window);
-        self.queue.write_buffer(
+    /// Passes raw device input, such as mouse motion, to the controller.
+    pub fn device_input(&mut self, event: &DeviceEvent) -> bool {
+        self.controller.process_device_events(event)
+    }
+
+    /// Replaces the controller that moves the camera in `update`.
+    pub fn set_controller(&mut self, controller: Box<dyn Controller>) {
+        self.controller = controller;
+    }
+
+    /// Whether the current controller wants the cursor grabbed, see
+    /// `controller::set_cursor_grab`.
+    pub fn wants_cursor_grab(&self) -> bool {
+        self.controller.wants_cursor_grab()
+    }
+
+    /// Advances the scene by the time that passed since the last frame.
+    pub fn update(&mut self, time: FrameTime) {
+        self.reload_assets();
+        self.previous_camera = self.camera;
+        self.controller.update_camera(&mut self.camera, time.delta);
+        self.upload_camera();
+    }
+
+    fn upload_camera(&mut self) {
+        self.write_camera_uniform(self.camera);
+    }
+
+    fn write_camera_uniform(&mut self, camera: Camera) {
+        self.camera_uniform.update_view_proj(&camera);
+        self.context.queue.write_buffer(
             &self.camera_buffer,
             0,
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let surface = self.surface.get_current_texture()?;
-        let view = surface
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+    pub fn render(&mut self) -> Result<()> {
+        self.render_interpolated(1.0)
+    }
+
+    /// Renders the scene `alpha` of the way from the state before the last
+    /// `update` to the current one. Used when updates run at a fixed rate
+    /// that doesn't match the frame rate, see `time::FixedTimestep`.
+    pub fn render_interpolated(&mut self, alpha: f32) -> Result<()> {
+        let camera = self.camera.interpolate(&self.previous_camera, alpha);
+        self.write_camera_uniform(camera);
+        self.shadows
+            .update(&self.context.queue, &camera, self.lights.shadow_caster());
+        self.skybox.update(&self.context.queue, &camera);
+
+        let frame = match &self.target {
+            RenderTarget::Surface(surface) => Some(surface.get_current_texture()?),
+            RenderTarget::Offscreen(_) => None,
+        };
+        let surface_view;
+        let view = match (&frame, &self.target) {
+            (Some(frame), _) => {
+                surface_view = frame
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                &surface_view
+            }
+            (None, RenderTarget::Offscreen(offscreen)) => &offscreen.view,
+            (None, RenderTarget::Surface(_)) => unreachable!(),
+        };
         let mut encoder = self
+            .context
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Command Enconder"),
             });
+        let draw_calls = self.draw_calls();
+        for (cascade_view, cascade_bind_group) in self.shadows.cascades() {
+            let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: cascade_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            shadow_pass.set_pipeline(&self.shadows.pipeline);
+            shadow_pass.set_bind_group(0, cascade_bind_group, &[]);
+            for call in &draw_calls {
+                shadow_pass.set_vertex_buffer(1, call.instance_buffer.slice(..));
+                draw_mesh_instanced(&mut shadow_pass, call.mesh, call.instances.clone());
+            }
+        }
+
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(self.clear_color),
+                        // Every pixel is covered by the geometry or the sky
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
                         store: true,
                     },
                 })],
-                depth_stencil_attachment: None,
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
             });
 
-            render_pass.set_pipeline(&self.render_pipeline);
-
-            match self.texture_load {
-                TextureLoad::TREE => {
-                    render_pass.set_bind_group(0, &self.tree_bind_group, &[]);
-                }
-                TextureLoad::DIRT => {
-                    render_pass.set_bind_group(0, &self.dirt_bind_group, &[]);
+            // Meshes without a material are drawn with the selected texture
+            let fallback = match self.texture_load {
+                TextureLoad::Array => (&self.array_pipeline, &self.array_bind_group),
+                TextureLoad::Custom(texture) => {
+                    (&self.render_pipeline, &self.texture_bind_groups[&texture])
                 }
-            }
+            };
+            render_pass.set_pipeline(fallback.0);
             render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.square_vertex_buffer.slice(..));
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            render_pass.set_bind_group(2, &self.lights.bind_group, &[]);
+            render_pass.set_bind_group(3, &self.shadows.bind_group, &[]);
 
-            render_pass.set_index_buffer(
-                self.square_index_buffer.slice(..),
-                wgpu::IndexFormat::Uint16,
-            );
-            render_pass.draw_indexed(0..SQUARE_INDICES.len() as u32, 0, 0..self.instances.len() as _);
+            for call in &draw_calls {
+                self.bind_material(&mut render_pass, call, fallback);
+                render_pass.set_vertex_buffer(1, call.instance_buffer.slice(..));
+                draw_mesh_instanced(&mut render_pass, call.mesh, call.instances.clone());
+            }
+            // Last, so the depth test skips every pixel covered by geometry
+            self.skybox.draw(&mut render_pass);
+        }
+
+        if let RenderTarget::Offscreen(offscreen) = &self.target {
+            offscreen.copy_to_buffer(&mut encoder);
         }
 
         // submit will accept anything that implements IntoIter
-        self.queue.submit(std::iter::once(encoder.finish()));
-        surface.present();
+        self.context.queue.submit(std::iter::once(encoder.finish()));
+        if let Some(frame) = frame {
+            frame.present();
+        }
+        Ok(())
+    }
+
+    /// Every mesh to draw this frame, in both the shadow and the main pass.
+    fn draw_calls(&self) -> Vec<DrawCall<'_>> {
+        let mut draw_calls = Vec::new();
+        if !self.instances.is_empty() {
+            let instances = 0..self.instances.len() as u32;
+            match self.model.and_then(|model| self.assets.get(model)) {
+                Some(model) => draw_calls.extend(model.meshes.iter().map(|mesh| DrawCall {
+                    mesh,
+                    model: Some(model),
+                    instance_buffer: &self.instance_buffer,
+                    instances: instances.clone(),
+                    mirrored: false,
+                })),
+                None => draw_calls.push(DrawCall {
+                    mesh: &self.mesh,
+                    model: None,
+                    instance_buffer: &self.instance_buffer,
+                    instances,
+                    mirrored: false,
+                }),
+            }
+        }
+        if let Some(scene) = self.scene.and_then(|scene| self.assets.get(scene)) {
+            for object in &scene.objects {
+                draw_calls.extend(scene.model.meshes[object.meshes.clone()].iter().map(|mesh| DrawCall {
+                    mesh,
+                    model: Some(&scene.model),
+                    instance_buffer: &object.instance_buffer,
+                    instances: 0..object.instances.len() as u32,
+                    mirrored: object.mirrored,
+                }));
+            }
+        }
+        draw_calls
+    }
+
+    /// Switches to the pipeline the mesh's material is drawn with and binds
+    /// the material, or the `fallback` pipeline and texture if the mesh has
+    /// none.
+    fn bind_material<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        call: &DrawCall<'a>,
+        fallback: (&'a wgpu::RenderPipeline, &'a wgpu::BindGroup),
+    ) {
+        let (pipeline, bind_group) = match call.model {
+            Some(model) if model.is_pbr(call.mesh) => {
+                let double_sided = model
+                    .material(call.mesh)
+                    .and_then(|material| material.pbr.as_ref())
+                    .is_some_and(|pbr| pbr.double_sided);
+                let cull_mode = if double_sided { None } else { Some(wgpu::Face::Back) };
+                let front_face = if call.mirrored { wgpu::FrontFace::Cw } else { wgpu::FrontFace::Ccw };
+                let pipeline = &self.pbr_pipelines[&(cull_mode, front_face)];
+                (pipeline, model.bind_group(call.mesh, fallback.1))
+            }
+            Some(model) if call.mesh.material.is_some() => {
+                (&self.render_pipeline, model.bind_group(call.mesh, fallback.1))
+            }
+            _ => fallback,
+        };
+        render_pass.set_pipeline(pipeline);
+        // The other groups stay bound, both pipelines use the same layouts
+        // for them
+        render_pass.set_bind_group(0, bind_group, &[]);
+    }
+
+    /// Reads back the last frame rendered by a headless state.
+    pub fn read_frame(&self) -> anyhow::Result<image::RgbaImage> {
+        match &self.target {
+            RenderTarget::Offscreen(offscreen) => offscreen.read(&self.context.device),
+            RenderTarget::Surface(_) => anyhow::bail!("Only headless states can read back frames"),
+        }
+    }
+
+    /// Reads back the last frame rendered by a headless state and saves it
+    /// to `path`. The image format is picked from the file extension.
+    pub fn save_frame<P: AsRef<std::path::Path>>(&self, path: P) -> anyhow::Result<()> {
+        self.read_frame()?.save(path)?;
         Ok(())
     }
 
     pub fn get_size(&self) -> winit::dpi::PhysicalSize<u32> {
         self.size
     }
+
+    /// Replaces the camera and uploads its matrix right away, so the next
+    /// frame uses it even if `update` isn't called.
+    ///
+    /// The camera's viewport is replaced with the one of this state's target.
+    pub fn set_camera(&mut self, camera: Camera) {
+        let viewport = self.camera.viewport;
+        self.camera = Camera { viewport, ..camera };
+        self.previous_camera = self.camera;
+        self.upload_camera();
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    /// Switches between e.g. perspective and orthographic projection while
+    /// keeping the camera where it is.
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.camera.projection = projection;
+        self.upload_camera();
+    }
+
+    /// Replaces the instances drawn every frame.
+    pub fn set_instances(&mut self, instances: Vec<Instance>) {
+        self.instance_buffer = self.context.upload_instances(&instances);
+        self.instances = instances;
+    }
+
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    /// Replaces the mesh drawn for every instance. Any model set with
+    /// `set_model` is dropped.
+    pub fn set_mesh(&mut self, mesh: Mesh) {
+        self.mesh = mesh;
+        self.replace_model(None);
+    }
+
+    /// Draws every mesh of `model` for every instance, in place of the mesh.
+    pub fn set_model(&mut self, model: Model) {
+        let model = self.assets.add(model);
+        self.replace_model(Some(model));
+    }
+
+    /// Loads an OBJ file with `Model::load_obj` and draws it for every
+    /// instance. A file that is already loaded isn't read again.
+    pub fn load_model<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<()> {
+        let model = self.assets.load_model(
+            &self.context,
+            &self.texture_bind_group_layout,
+            path,
+        )?;
+        self.replace_model(Some(model));
+        Ok(())
+    }
+
+    /// Draws `model` in place of the current one, whose reference is
+    /// released.
+    fn replace_model(&mut self, model: Option<Handle<Model>>) {
+        if let Some(previous) = std::mem::replace(&mut self.model, model) {
+            self.assets.release(previous);
+        }
+    }
+
+    /// Draws the objects of `scene` at their own instances, next to the
+    /// mesh or model drawn for `instances`.
+    pub fn set_scene(&mut self, scene: Scene) {
+        let scene = self.assets.add(scene);
+        self.replace_scene(scene);
+    }
+
+    /// Imports a glTF file with `Scene::load_gltf` and draws it. A file
+    /// that is already imported isn't read again.
+    pub fn load_scene<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<()> {
+        let scene = self.assets.load_scene(
+            &self.context,
+            &self.pbr_material_layout,
+            path,
+        )?;
+        self.replace_scene(scene);
+        Ok(())
+    }
+
+    /// Draws `scene` in place of the current one, whose reference is
+    /// released.
+    fn replace_scene(&mut self, scene: Handle<Scene>) {
+        if let Some(previous) = self.scene.replace(scene) {
+            self.assets.release(previous);
+        }
+    }
+
+    /// Adds a light to the scene, or returns `None` if there are
+    /// `light::MAX_LIGHTS` already. The scene is drawn unlit until the
+    /// first light is added.
+    pub fn add_light(&mut self, light: Light) -> Option<LightId> {
+        self.lights.add(&self.context.queue, light)
+    }
+
+    /// Replaces a light, e.g. to move it. Returns false if it was removed.
+    pub fn set_light(&mut self, id: LightId, light: Light) -> bool {
+        self.lights.set(&self.context.queue, id, light)
+    }
+
+    pub fn remove_light(&mut self, id: LightId) -> Option<Light> {
+        self.lights.remove(&self.context.queue, id)
+    }
+
+    pub fn light(&self, id: LightId) -> Option<&Light> {
+        self.lights.get(id)
+    }
+
+    /// Sets the light color that reaches every lit surface.
+    pub fn set_ambient_light(&mut self, ambient: [f32; 3]) {
+        self.lights.set_ambient(&self.context.queue, ambient);
+    }
+
+    /// Replaces the sky with a cube texture, see `Texture::from_cube_faces`
+    /// and `Texture::from_equirectangular`.
+    pub fn set_skybox(&mut self, texture: texture::Texture) {
+        self.skybox.set_texture(&self.context.device, texture);
+    }
+
+    /// Loads six images as the faces of the sky, in the order +X, -X, +Y,
+    /// -Y, +Z, -Z.
+    pub fn load_skybox<P: AsRef<std::path::Path>>(&mut self, faces: [P; 6]) -> Result<()> {
+        let load = |face: &P| -> Result<image::DynamicImage> { Ok(image::load_from_memory(&std::fs::read(face)?)?) };
+        let [px, nx, py, ny, pz, nz] = &faces;
+        let images = [load(px)?, load(nx)?, load(py)?, load(ny)?, load(pz)?, load(nz)?];
+        let texture = texture::Texture::from_cube_faces(&self.context.device, &self.context.queue, &images, Some("Sky"))?;
+        self.set_skybox(texture);
+        Ok(())
+    }
+
+    /// Loads an equirectangular panorama as the sky. Each face of the cube
+    /// gets a quarter of the panorama's width.
+    pub fn load_equirectangular_skybox<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<()> {
+        let image = image::load_from_memory(&std::fs::read(path)?)?;
+        let face_size = (image.width() / 4).max(1);
+        let texture = texture::Texture::from_equirectangular(
+            &self.context.device,
+            &self.context.queue,
+            &image,
+            face_size,
+            Some("Sky"),
+        )?;
+        self.set_skybox(texture);
+        Ok(())
+    }
+
+    /// Changes how shadows are rendered, see `ShadowSettings`.
+    pub fn set_shadow_settings(&mut self, settings: ShadowSettings) {
+        self.shadows.set_settings(&self.context.device, settings);
+    }
+
+    pub fn shadow_settings(&self) -> &ShadowSettings {
+        self.shadows.settings()
+    }
+
+    /// Layout of the texture bind group (group 0), for materials created
+    /// outside of the state.
+    pub fn texture_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.texture_bind_group_layout
+    }
+
+    /// Layout of the material bind group (group 0) of the PBR pipeline,
+    /// for `Material::new_pbr`.
+    pub fn pbr_material_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.pbr_material_layout
+    }
+
+    /// Selects the texture meshes without a material are drawn with.
+    /// Returns false, keeping the current one, if it was released or
+    /// belongs to another state.
+    pub fn set_texture(&mut self, texture_load: TextureLoad) -> bool {
+        if let TextureLoad::Custom(texture) = texture_load {
+            if !self.texture_bind_groups.contains_key(&texture) {
+                return false;
+            }
+        }
+        self.texture_load = texture_load;
+        true
+    }
+
+    /// Makes `texture` available to the pipeline. Select it with the
+    /// returned value through `set_texture`.
+    pub fn add_texture(&mut self, texture: texture::Texture) -> TextureLoad {
+        let texture = self.assets.add(texture);
+        self.bind_texture(texture)
+    }
+
+    /// Loads an image with `Assets::load_texture` and makes it available to
+    /// the pipeline like `add_texture`. A file that is already loaded with
+    /// the same options isn't read again.
+    pub fn load_texture<P: AsRef<std::path::Path>>(
+        &mut self,
+        path: P,
+        options: &texture::TextureOptions,
+    ) -> Result<TextureLoad> {
+        let texture =
+            self.assets
+                .load_texture(&self.context, path, options)?;
+        Ok(self.bind_texture(texture))
+    }
+
+    /// Releases a texture added with `add_texture` or `load_texture`. Once
+    /// every reference is gone it can no longer be selected, and if it was
+    /// selected the texture array is drawn again.
+    pub fn release_texture(&mut self, texture_load: TextureLoad) {
+        let TextureLoad::Custom(texture) = texture_load else {
+            return;
+        };
+        // Only release references this state handed out
+        if !self.texture_bind_groups.contains_key(&texture) {
+            return;
+        }
+        if self.assets.release(texture).is_some() {
+            self.texture_bind_groups.remove(&texture);
+            if self.texture_load == texture_load {
+                self.texture_load = TextureLoad::Array;
+            }
+        }
+    }
+
+    fn bind_texture(&mut self, texture: Handle<texture::Texture>) -> TextureLoad {
+        if !self.texture_bind_groups.contains_key(&texture) {
+            let bind_group = self.assets.get(texture).unwrap().create_bind_group(
+                &self.context.device,
+                &self.texture_bind_group_layout,
+                "custom_bind_group",
+            );
+            self.texture_bind_groups.insert(texture, bind_group);
+        }
+        TextureLoad::Custom(texture)
+    }
+
+    /// Reloads textures, models and scenes whose files change on disk,
+    /// from the next `update` on. See `Assets::reload_changed`.
+    pub fn watch_assets(&mut self) -> Result<()> {
+        self.assets.watch()
+    }
+
+    /// Reloads the changed assets and creates the bind groups of the
+    /// reloaded textures again. Reloaded models and scenes come with their
+    /// own.
+    fn reload_assets(&mut self) {
+        let reloaded = self.assets.reload_changed(
+            &self.context,
+            &self.texture_bind_group_layout,
+            &self.pbr_material_layout,
+        );
+        for asset in reloaded {
+            let Reloaded::Texture(texture) = asset else {
+                continue;
+            };
+            let asset = self.assets.get(texture).unwrap();
+            if texture == self.array_texture {
+                self.array_bind_group = asset.create_bind_group(
+                    &self.context.device,
+                    &self.texture_array_layout,
+                    "array_bind_group",
+                );
+            } else if self.texture_bind_groups.contains_key(&texture) {
+                let bind_group = asset.create_bind_group(
+                    &self.context.device,
+                    &self.texture_bind_group_layout,
+                    "custom_bind_group",
+                );
+                self.texture_bind_groups.insert(texture, bind_group);
+            }
+        }
+    }
+
+    /// The textures, models and shaders loaded for this state.
+    pub fn assets(&self) -> &Assets {
+        &self.assets
+    }
+
+    /// The GPU context, e.g. to upload meshes and textures for this state.
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+}
+
+/// A mesh drawn for a range of instances.
+struct DrawCall<'a> {
+    mesh: &'a Mesh,
+    /// Where the mesh's material comes from. `None` for the plain mesh,
+    /// which is drawn with the selected texture
+    model: Option<&'a Model>,
+    instance_buffer: &'a wgpu::Buffer,
+    instances: std::ops::Range<u32>,
+    /// Whether the instances turn the winding of the triangles around
+    mirrored: bool,
+}
+
+fn draw_mesh_instanced<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    mesh: &'a Mesh,
+    instances: std::ops::Range<u32>,
+) {
+    render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+    render_pass.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+    render_pass.draw_indexed(0..mesh.num_indices, 0, instances);
+}
+
+/// A pipeline drawing `Vertex` meshes with instancing, using the
+/// `vertex_main` and `fragment_entry` entry points of `shader`.
+/// How the triangles of a pipeline are culled, and which winding faces
+/// the front.
+type Faces = (Option<wgpu::Face>, wgpu::FrontFace);
+
+/// Culls the back of counter-clockwise triangles.
+const ONE_SIDED: Faces = (Some(wgpu::Face::Back), wgpu::FrontFace::Ccw);
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    fragment_entry: &str,
+    format: wgpu::TextureFormat,
+    (cull_mode, front_face): Faces,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vertex_main",
+            buffers: &[Vertex::desc(), InstanceRaw::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: fragment_entry,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face,
+            cull_mode,
+            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+            polygon_mode: wgpu::PolygonMode::Fill,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            // Fragments closer to the camera replace the ones behind them
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+/// The tree and dirt textures in one array, in the order of `TREE_LAYER`
+/// and `DIRT_LAYER`. They are read from the `assets` folder if there is
+/// one, so they are reloaded when edited, and from the copies built into
+/// the binary otherwise.
+fn load_texture_array(
+    assets: &mut Assets,
+    context: &Context,
+) -> Result<Handle<texture::Texture>> {
+    let options = texture::TextureOptions::pixel_art();
+    if let Some(folder) = assets_folder() {
+        let paths = [folder.join("tree.png"), folder.join("dirt.png")];
+        return assets.load_texture_layers(context, &paths, &options);
+    }
+    let layers = [
+        image::load_from_memory(include_bytes!("../assets/tree.png"))?,
+        image::load_from_memory(include_bytes!("../assets/dirt.png"))?,
+    ];
+    let texture = texture::Texture::from_layers(context, &layers, Some("Tree and Dirt"), &options)?;
+    Ok(assets.add(texture))
+}
+
+/// The first `assets` folder with the tree and dirt textures: next to the
+/// executable, in the working directory or, in debug builds, in the
+/// repository the binary was built from.
+fn assets_folder() -> Option<std::path::PathBuf> {
+    let next_to_executable = std::env::current_exe()
+        .ok()
+        .and_then(|executable| Some(executable.parent()?.join("assets")));
+    let repository = cfg!(debug_assertions)
+        .then(|| std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets"));
+    next_to_executable
+        .into_iter()
+        .chain([std::path::PathBuf::from("assets")])
+        .chain(repository)
+        .find(|folder| folder.join("tree.png").is_file() && folder.join("dirt.png").is_file())
 }
@@ -1,6 +1,103 @@
-use anyhow::*;
 use image::GenericImageView;
 
+use crate::{context::Context, error::Result, mipmap};
+
+/// How the smaller mip levels of an image are made when it is uploaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Mipmaps {
+    /// Only the full size level
+    None,
+    /// Drawn on the GPU, see `mipmap::Mipmapper`. Formats that
+    /// can't be rendered to are downscaled on the CPU instead
+    #[default]
+    Gpu,
+    /// Downscaled on the CPU before uploading, see `mipmap::downscale`
+    Cpu,
+}
+
+/// How an image is uploaded and sampled.
+///
+/// The default is what `Texture::from_image` uses: sRGB texels with a mip
+/// chain drawn on the GPU, clamped to the edges, magnified linearly and
+/// minified with the nearest texel of the nearest level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextureOptions {
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+    /// The most samples anisotropic filtering takes, 1 to turn it off.
+    /// Rounded down to a power of two, at most 16. Devices without support
+    /// for it ignore it
+    pub anisotropy: u8,
+    /// Whether the texels are sRGB colors, which are converted to linear
+    /// when sampled. Data like normal maps has to be `false`
+    pub srgb: bool,
+    pub mipmaps: Mipmaps,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            anisotropy: 1,
+            srgb: true,
+            mipmaps: Mipmaps::default(),
+        }
+    }
+}
+
+impl TextureOptions {
+    /// Filters linearly within and between mip levels, which hides the
+    /// switch from one level to the next.
+    pub fn trilinear() -> Self {
+        Self {
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Self::default()
+        }
+    }
+
+    /// Keeps texels sharp squares up close, but still blends them in the
+    /// distance so they don't shimmer.
+    pub fn pixel_art() -> Self {
+        Self {
+            mag_filter: wgpu::FilterMode::Nearest,
+            ..Self::trilinear()
+        }
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        if self.srgb {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        }
+    }
+
+    pub fn create_sampler(&self, device: &wgpu::Device, label: Option<&str>) -> wgpu::Sampler {
+        // wgpu only accepts powers of two
+        let anisotropy = self.anisotropy.clamp(1, 16);
+        let anisotropy = 1 << (u8::BITS - 1 - anisotropy.leading_zeros());
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            label,
+            address_mode_u: self.address_mode_u,
+            address_mode_v: self.address_mode_v,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter,
+            anisotropy_clamp: std::num::NonZeroU8::new(anisotropy).filter(|clamp| clamp.get() > 1),
+            ..Default::default()
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct Texture {
     pub texture: wgpu::Texture,
@@ -9,63 +106,282 @@ pub struct Texture {
 }
 
 impl Texture {
-    pub fn from_bytes(
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+    /// Creates a depth texture matching the size of the render target.
+    pub fn create_depth_texture(
         device: &wgpu::Device,
-        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::DEPTH_FORMAT,
+            // We only need TEXTURE_BINDING if we want to sample the depth
+            // buffer later on, e.g. to debug it
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    pub fn from_bytes(
+        context: &Context,
         bytes: &[u8],
         label: &str,
+    ) -> Result<Self> {
+        Self::from_bytes_with_options(context, bytes, label, &TextureOptions::default())
+    }
+
+    pub fn from_bytes_with_options(
+        context: &Context,
+        bytes: &[u8],
+        label: &str,
+        options: &TextureOptions,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        Self::from_image_with_options(context, &img, Some(label), options)
     }
 
+    /// A 1x1 texture of a single sRGB color, e.g. for materials without
+    /// an image.
+    pub fn from_color(
+        context: &Context,
+        color: [u8; 4],
+        label: &str,
+    ) -> Result<Self> {
+        let img = image::RgbaImage::from_pixel(1, 1, image::Rgba(color));
+        Self::from_image(context, &image::DynamicImage::ImageRgba8(img), Some(label))
+    }
+
+    /// Uploads an sRGB image with the default `TextureOptions`.
     pub fn from_image(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
+        context: &Context,
         img: &image::DynamicImage,
         label: Option<&str>,
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        Self::from_image_with_options(context, img, label, &TextureOptions::default())
+    }
+
+    /// Like `from_image`, but for data that isn't a color, like normal maps.
+    /// The texels are sampled as they are, without converting from sRGB.
+    pub fn from_linear_image(
+        context: &Context,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        let options = TextureOptions {
+            srgb: false,
+            ..TextureOptions::default()
+        };
+        Self::from_image_with_options(context, img, label, &options)
+    }
+
+    pub fn from_image_with_options(
+        context: &Context,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        options: &TextureOptions,
+    ) -> Result<Self> {
+        Self::from_layers(context, std::slice::from_ref(img), label, options)
+    }
+
+    /// Uploads `layers` as the layers of a texture array, viewed with
+    /// `TextureViewDimension::D2Array` when there is more than one. Layers
+    /// of another size than the first one are scaled to its size.
+    ///
+    /// The GL backend takes six square layers for a cube texture, so arrays
+    /// of six layers can't be sampled as arrays there.
+    pub fn from_layers(
+        context: &Context,
+        layers: &[image::DynamicImage],
+        label: Option<&str>,
+        options: &TextureOptions,
+    ) -> Result<Self> {
+        let Context { device, queue, .. } = context;
+        let (width, height) = match layers.first() {
+            Some(first) => first.dimensions(),
+            None => {
+                return Err(image::ImageError::Parameter(image::error::ParameterError::from_kind(
+                    image::error::ParameterErrorKind::DimensionMismatch,
+                ))
+                .into())
+            }
+        };
+        let layers = layers
+            .iter()
+            .map(|layer| match layer.dimensions() == (width, height) {
+                true => layer.to_rgba8(),
+                false => image::imageops::resize(layer, width, height, image::imageops::FilterType::Triangle),
+            })
+            .collect::<Vec<_>>();
+        let format = options.format();
+        let mipmaps = options.mipmaps;
+        let mip_level_count = match mipmaps {
+            Mipmaps::None => 1,
+            Mipmaps::Gpu | Mipmaps::Cpu => mipmap::mip_level_count(width, height),
+        };
+        let mipmaps = match mipmaps {
+            Mipmaps::Gpu if !context.mipmapper.supports(format) => Mipmaps::Cpu,
+            mipmaps => mipmaps,
+        };
 
         let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth_or_array_layers: 1,
+            width,
+            height,
+            depth_or_array_layers: layers.len() as u32,
         };
+        let mut usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST;
+        if mipmaps == Mipmaps::Gpu {
+            usage |= wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::RENDER_ATTACHMENT;
+        }
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+        });
+
+        let write_level = |layer: u32, level: u32, rgba: &image::RgbaImage| {
+            let (width, height) = rgba.dimensions();
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: level,
+                    origin: wgpu::Origin3d { x: 0, y: 0, z: layer },
+                },
+                rgba,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(4 * width),
+                    rows_per_image: std::num::NonZeroU32::new(height),
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        };
+        for (layer, rgba) in (0..).zip(&layers) {
+            write_level(layer, 0, rgba);
+            if mipmaps == Mipmaps::Cpu {
+                for (level, rgba) in (1..).zip(mipmap::downscale(rgba, mip_level_count)) {
+                    write_level(layer, level, &rgba);
+                }
+            }
+        }
+        if mipmaps == Mipmaps::Gpu {
+            context.mipmapper.generate(device, queue, &texture, format, size, mip_level_count);
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = options.create_sampler(device, label);
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
+    }
+
+    /// Uploads six square images of the same size as the faces of a cube
+    /// texture, viewed with `TextureViewDimension::Cube`. The faces are in
+    /// the order +X, -X, +Y, -Y, +Z, -Z, each as seen from the inside.
+    ///
+    /// Cube textures are looked up in a left-handed space, so the +Z and
+    /// -Z faces are swapped on upload. Shaders have to negate the Z of the
+    /// direction they sample with.
+    pub fn from_cube_faces(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[image::DynamicImage; 6],
+        label: Option<&str>,
+    ) -> Result<Self> {
+        let size = faces[0].width();
+        if faces.iter().any(|face| face.dimensions() != (size, size)) {
+            return Err(image::ImageError::Parameter(image::error::ParameterError::from_kind(
+                image::error::ParameterErrorKind::DimensionMismatch,
+            ))
+            .into());
+        }
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba8UnormSrgb,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
         });
+        for (layer, face) in [0, 1, 2, 3, 5, 4].into_iter().zip(faces) {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: 0, y: 0, z: layer },
+                },
+                &face.to_rgba8(),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(4 * size),
+                    rows_per_image: std::num::NonZeroU32::new(size),
+                },
+                wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: std::num::NonZeroU32::new(4 * dimensions.0),
-                rows_per_image: std::num::NonZeroU32::new(dimensions.1),
-            },
-            size,
-        );
-
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
             ..Default::default()
         });
@@ -76,4 +392,135 @@ impl Texture {
             sampler,
         })
     }
+
+    /// Uploads a panorama in the equirectangular projection (longitude
+    /// along the width, latitude along the height) as a cube texture with
+    /// `face_size` texels wide faces. The center of the image ends up at
+    /// -Z.
+    pub fn from_equirectangular(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        face_size: u32,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        let faces = equirectangular_to_cube_faces(&img.to_rgba8(), face_size);
+        Self::from_cube_faces(device, queue, &faces.map(image::DynamicImage::ImageRgba8), label)
+    }
+
+    /// A cube texture with all faces in a single sRGB color.
+    pub fn cube_from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+    ) -> Result<Self> {
+        let face = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
+        let faces = [(); 6].map(|_| face.clone());
+        Self::from_cube_faces(device, queue, &faces, Some(label))
+    }
+
+    /// Binds the view and sampler to a layout with the texture at binding 0
+    /// and the sampler at binding 1, like `texture_bind_group_layout`.
+    pub fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        label: &str,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&self.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+            label: Some(label),
+        })
+    }
+}
+
+/// The world direction from the center of a cube through texel (`x`, `y`)
+/// of `face`, in the order of `Texture::from_cube_faces`.
+fn cube_face_direction(face: usize, x: u32, y: u32, size: u32) -> [f32; 3] {
+    let s = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+    let t = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+    match face {
+        0 => [1.0, -t, s],
+        1 => [-1.0, -t, -s],
+        2 => [s, 1.0, -t],
+        3 => [s, -1.0, t],
+        4 => [-s, -t, 1.0],
+        _ => [s, -t, -1.0],
+    }
+}
+
+/// Resamples an equirectangular panorama into the six faces of a cube, in
+/// the order `Texture::from_cube_faces` expects.
+fn equirectangular_to_cube_faces(img: &image::RgbaImage, face_size: u32) -> [image::RgbaImage; 6] {
+    use std::f32::consts::PI;
+
+    let (width, height) = img.dimensions();
+    // Bilinear filtering, wrapping around horizontally
+    let sample = |u: f32, v: f32| {
+        let x = u * width as f32 - 0.5;
+        let y = (v * height as f32 - 0.5).clamp(0.0, height as f32 - 1.0);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let texel = |x: f32, y: f32| {
+            let x = (x as i64).rem_euclid(width as i64) as u32;
+            let y = (y as u32).min(height - 1);
+            img.get_pixel(x, y).0.map(f32::from)
+        };
+        let [a, b, c, d] = [texel(x0, y0), texel(x0 + 1.0, y0), texel(x0, y0 + 1.0), texel(x0 + 1.0, y0 + 1.0)];
+        let mut color = [0; 4];
+        for i in 0..4 {
+            let top = a[i] + (b[i] - a[i]) * fx;
+            let bottom = c[i] + (d[i] - c[i]) * fx;
+            color[i] = (top + (bottom - top) * fy).round() as u8;
+        }
+        image::Rgba(color)
+    };
+
+    let face = |face: usize| {
+        image::RgbaImage::from_fn(face_size, face_size, |x, y| {
+            let [dx, dy, dz] = cube_face_direction(face, x, y, face_size);
+            let longitude = dx.atan2(-dz);
+            let latitude = (dy / (dx * dx + dy * dy + dz * dz).sqrt()).asin();
+            sample(0.5 + longitude / (2.0 * PI), 0.5 - latitude / PI)
+        })
+    };
+    [face(0), face(1), face(2), face(3), face(4), face(5)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equirectangular_faces_look_the_right_way() {
+        // Top half red, bottom half blue, with a green stripe just left of
+        // the middle of the top half, which is straight ahead (-Z)
+        let panorama = image::RgbaImage::from_fn(64, 32, |x, y| match (x, y) {
+            (24..=31, 0..=15) => image::Rgba([0, 255, 0, 255]),
+            (_, 0..=15) => image::Rgba([255, 0, 0, 255]),
+            _ => image::Rgba([0, 0, 255, 255]),
+        });
+        let faces = equirectangular_to_cube_faces(&panorama, 8);
+        let [right, left, up, down, back, front] = &faces;
+        assert_eq!(up.get_pixel(4, 4).0, [255, 0, 0, 255]);
+        assert_eq!(down.get_pixel(4, 4).0, [0, 0, 255, 255]);
+        // Just above the horizon, not mirrored
+        assert_eq!(front.get_pixel(2, 2).0, [0, 255, 0, 255]);
+        assert_eq!(front.get_pixel(6, 2).0, [255, 0, 0, 255]);
+        for side in [right, left, back] {
+            assert_eq!(side.get_pixel(4, 2).0, [255, 0, 0, 255]);
+            assert_eq!(side.get_pixel(4, 6).0, [0, 0, 255, 255]);
+        }
+    }
 }
@@ -0,0 +1,170 @@
+use std::time::{Duration, Instant};
+
+/// Timing information for a single frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameTime {
+    /// Time since the previous frame
+    pub delta: Duration,
+    /// Time since the clock was started
+    pub elapsed: Duration,
+}
+
+impl FrameTime {
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+
+    pub fn elapsed_seconds(&self) -> f32 {
+        self.elapsed.as_secs_f32()
+    }
+}
+
+/// Measures the time between frames.
+pub struct Clock {
+    start: Instant,
+    last_tick: Instant,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            last_tick: now,
+        }
+    }
+
+    /// Starts a new frame and returns how long the previous one took.
+    pub fn tick(&mut self) -> FrameTime {
+        let now = Instant::now();
+        let delta = now - self.last_tick;
+        self.last_tick = now;
+        FrameTime {
+            delta,
+            elapsed: now - self.start,
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs the simulation at a fixed rate, independent of the frame rate.
+///
+/// Real frame time is added to an accumulator with `accumulate`, then
+/// `next_step` hands out fixed steps until less than one step is left. The
+/// remainder, as a fraction of a step, is the `alpha` to interpolate the
+/// rendered state with.
+pub struct FixedTimestep {
+    step: Duration,
+    accumulator: Duration,
+    max_steps_per_frame: u32,
+    /// Simulated time, the sum of all steps taken
+    elapsed: Duration,
+}
+
+impl FixedTimestep {
+    /// Creates a timestep that updates `hz` times per second.
+    ///
+    /// Panics unless `hz` is positive and finite, with steps of at least a
+    /// nanosecond.
+    pub fn new(hz: f64) -> Self {
+        assert!(
+            hz > 0.0 && hz.is_finite(),
+            "the update rate has to be positive and finite, got {}",
+            hz
+        );
+        let step = Duration::from_secs_f64(1.0 / hz);
+        assert!(
+            !step.is_zero(),
+            "an update rate of {} Hz makes the step shorter than 1ns",
+            hz
+        );
+        Self {
+            step,
+            accumulator: Duration::ZERO,
+            max_steps_per_frame: 8,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Caps how many steps may run for a single frame. When updates take
+    /// longer than the time they simulate, every frame would otherwise
+    /// run more steps than the last (the spiral of death). Time beyond the
+    /// cap is dropped, so the simulation slows down instead.
+    pub fn with_max_steps_per_frame(mut self, max_steps_per_frame: u32) -> Self {
+        self.max_steps_per_frame = max_steps_per_frame.max(1);
+        self
+    }
+
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    /// Adds the real time that passed since the last frame.
+    pub fn accumulate(&mut self, frame_delta: Duration) {
+        let max_accumulated = self.step * self.max_steps_per_frame;
+        self.accumulator = (self.accumulator + frame_delta).min(max_accumulated);
+    }
+
+    /// Takes one step off the accumulator, if a whole one is left.
+    ///
+    /// The returned time only depends on the number of steps taken, so
+    /// replaying the same input gives the same simulation.
+    pub fn next_step(&mut self) -> Option<FrameTime> {
+        if self.accumulator < self.step {
+            return None;
+        }
+        self.accumulator -= self.step;
+        self.elapsed += self.step;
+        Some(FrameTime {
+            delta: self.step,
+            elapsed: self.elapsed,
+        })
+    }
+
+    /// How far the accumulator is into the next step, from 0 to 1.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.step.as_secs_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_timestep_runs_whole_steps_and_keeps_the_remainder() {
+        let mut timestep = FixedTimestep::new(10.0);
+        timestep.accumulate(Duration::from_millis(250));
+
+        let steps = std::iter::from_fn(|| timestep.next_step()).collect::<Vec<_>>();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[1].elapsed, Duration::from_millis(200));
+        assert!((timestep.alpha() - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn fixed_timestep_drops_time_beyond_max_steps() {
+        let mut timestep = FixedTimestep::new(10.0).with_max_steps_per_frame(3);
+        timestep.accumulate(Duration::from_secs(10));
+
+        assert_eq!(std::iter::from_fn(|| timestep.next_step()).count(), 3);
+        assert_eq!(timestep.alpha(), 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "positive and finite")]
+    fn fixed_timestep_rejects_a_zero_rate() {
+        FixedTimestep::new(0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "positive and finite")]
+    fn fixed_timestep_rejects_an_infinite_rate() {
+        FixedTimestep::new(f64::INFINITY);
+    }
+}
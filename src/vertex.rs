@@ -1,54 +1,258 @@
+use crate::{assets::Handle, instance::InstanceRaw, texture::Texture};
+
+/// A struct that is uploaded to a vertex buffer as is.
+///
+/// Implement it with `vertex_layout!`, which takes the attribute offsets
+/// from the struct itself so they can't drift from its layout, and checks
+/// the attributes at compile time.
+pub trait VertexLayout: bytemuck::Pod {
+    const STEP_MODE: wgpu::VertexStepMode;
+    const ATTRIBUTES: &'static [wgpu::VertexAttribute];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: Self::STEP_MODE,
+            attributes: Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// Field types that can be read as a single vertex attribute.
+pub trait AttributeType {
+    const FORMAT: wgpu::VertexFormat;
+}
+
+macro_rules! attribute_types {
+    ($($ty:ty => $format:ident),* $(,)?) => {
+        $(impl AttributeType for $ty {
+            const FORMAT: wgpu::VertexFormat = wgpu::VertexFormat::$format;
+        })*
+    };
+}
+
+attribute_types! {
+    f32 => Float32,
+    [f32; 2] => Float32x2,
+    [f32; 3] => Float32x3,
+    [f32; 4] => Float32x4,
+    u32 => Uint32,
+    [u32; 2] => Uint32x2,
+    [u32; 3] => Uint32x3,
+    [u32; 4] => Uint32x4,
+    // Colors, read as 0.0 to 1.0 in the shader
+    [u8; 4] => Unorm8x4,
+}
+
+/// The format of the field `field` points at. Only used by `vertex_layout!`,
+/// `field` is never called.
+pub const fn format_of<V, T: AttributeType>(_field: fn(&V) -> &T) -> wgpu::VertexFormat {
+    T::FORMAT
+}
+
+/// Panics (at compile time, when used in a constant) if two attributes
+/// share a location or overlap, or if one doesn't fit in `stride`.
+pub const fn check_attributes(attributes: &[wgpu::VertexAttribute], stride: usize) {
+    let mut i = 0;
+    while i < attributes.len() {
+        let a = &attributes[i];
+        if a.offset + a.format.size() > stride as u64 {
+            panic!("vertex attribute doesn't fit in the struct");
+        }
+        let mut j = i + 1;
+        while j < attributes.len() {
+            let b = &attributes[j];
+            if a.shader_location == b.shader_location {
+                panic!("two vertex attributes use the same shader location");
+            }
+            if a.offset < b.offset + b.format.size() && b.offset < a.offset + a.format.size() {
+                panic!("two vertex attributes overlap");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+/// Panics (at compile time, when used in a constant) if a per-vertex
+/// attribute uses one of the shader locations of `InstanceRaw`, which is
+/// bound next to it in the render pipeline.
+pub const fn check_instance_locations(attributes: &[wgpu::VertexAttribute]) {
+    let instance_attributes = <InstanceRaw as VertexLayout>::ATTRIBUTES;
+    let mut i = 0;
+    while i < attributes.len() {
+        let mut j = 0;
+        while j < instance_attributes.len() {
+            if attributes[i].shader_location == instance_attributes[j].shader_location {
+                panic!("vertex attribute uses a shader location of the instance attributes");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+/// Implements `VertexLayout` for a `#[repr(C)]` struct from a list of
+/// `field => shader_location`. Matrices take one location per column,
+/// listed as `field[column] => shader_location`.
+///
+/// ```ignore
+/// vertex_layout!(Vertex, Vertex {
+///     position => 0,
+///     uv => 1,
+/// });
+/// ```
+///
+/// Per-vertex layouts are also checked against the locations of `InstanceRaw`.
+#[macro_export]
+macro_rules! vertex_layout {
+    ($ty:ty, $step_mode:ident { $($field:ident $([$column:literal])? => $location:literal),* $(,)? }) => {
+        impl $crate::vertex::VertexLayout for $ty {
+            const STEP_MODE: wgpu::VertexStepMode = wgpu::VertexStepMode::$step_mode;
+            const ATTRIBUTES: &'static [wgpu::VertexAttribute] = &[$({
+                const FORMAT: wgpu::VertexFormat =
+                    $crate::vertex::format_of(|vertex: &$ty| &vertex.$field $([$column])?);
+                wgpu::VertexAttribute {
+                    offset: std::mem::offset_of!($ty, $field) as wgpu::BufferAddress
+                        $(+ $column * FORMAT.size())?,
+                    shader_location: $location,
+                    format: FORMAT,
+                }
+            }),*];
+        }
+
+        const _: () = {
+            use $crate::vertex::VertexLayout;
+            $crate::vertex::check_attributes(<$ty>::ATTRIBUTES, std::mem::size_of::<$ty>());
+            if matches!(<$ty>::STEP_MODE, wgpu::VertexStepMode::Vertex) {
+                $crate::vertex::check_instance_locations(<$ty>::ATTRIBUTES);
+            }
+        };
+    };
+}
+
+/// The vertex format of the render pipeline.
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Vertex {
     pub position: [f32; 3],
     pub uv: [f32; 2],
+    pub normal: [f32; 3],
+    /// Tangent along +u. `w` is the handedness: the bitangent (along +v)
+    /// is `cross(normal, tangent) * w`, like in glTF.
+    pub tangent: [f32; 4],
 }
 
-impl Vertex {
-    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x2,
-                },
-            ],
-        }
-    }
+vertex_layout!(Vertex, Vertex {
+    position => 0,
+    uv => 1,
+    normal => 2,
+    tangent => 3,
+});
+
+/// A `Vertex` with a color, e.g. for vertex painted meshes.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ColorVertex {
+    pub position: [f32; 3],
+    pub uv: [f32; 2],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 4],
+    /// Linear RGBA
+    pub color: [f32; 4],
 }
+
+vertex_layout!(ColorVertex, Vertex {
+    position => 0,
+    uv => 1,
+    normal => 2,
+    tangent => 3,
+    color => 4,
+});
+
+/// A `Vertex` with a second set of texture coordinates, e.g. for lightmaps
+/// or detail textures.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MultiUvVertex {
+    pub position: [f32; 3],
+    pub uv: [f32; 2],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 4],
+    pub uv1: [f32; 2],
+}
+
+vertex_layout!(MultiUvVertex, Vertex {
+    position => 0,
+    uv => 1,
+    normal => 2,
+    tangent => 3,
+    // Past the instance attributes, so it can be combined with a color at 4
+    uv1 => 10,
+});
+
 pub const SQUARE_VERTICES: &[Vertex] = &[
     Vertex {
         position: [-0.8, 0.8, 0.0], // top right
         // top left
         // bottom left
         uv: [0.0, 1.0],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [1.0, 0.0, 0.0, 1.0],
     }, // top left
     Vertex {
         position: [0.8, 0.8, 0.0],
         uv: [1.0, 1.0],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [1.0, 0.0, 0.0, 1.0],
     }, // top right
     Vertex {
         position: [0.8, -0.8, 0.0],
         uv: [0.0, 0.0],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [1.0, 0.0, 0.0, 1.0],
     }, // bottom right
     Vertex {
         position: [-0.8, -0.8, 0.0],
         uv: [0.0, 0.0],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [1.0, 0.0, 0.0, 1.0],
     }, // bottom left
 ];
 pub const SQUARE_INDICES: &[u16] = &[1, 0, 3, 3, 2, 1];
 
-#[derive(Debug)]
+/// Layers of the texture array `TextureLoad::Array` draws with.
+pub const TREE_LAYER: u32 = 0;
+pub const DIRT_LAYER: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TextureLoad {
-    TREE,
-    DIRT,
+    /// The tree and dirt textures in one array. Every instance picks its
+    /// layer with `Instance::texture_layer`
+    Array,
+    /// A texture added with `State::add_texture` or `State::load_texture`
+    Custom(Handle<Texture>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layouts_follow_the_struct_layout() {
+        let offsets = |attributes: &[wgpu::VertexAttribute]| {
+            attributes.iter().map(|a| (a.shader_location, a.offset)).collect::<Vec<_>>()
+        };
+        assert_eq!(offsets(Vertex::ATTRIBUTES), [(0, 0), (1, 12), (2, 20), (3, 32)]);
+        assert_eq!(Vertex::desc().array_stride, 48);
+        assert_eq!(offsets(MultiUvVertex::ATTRIBUTES)[4], (10, 48));
+        assert_eq!(
+            offsets(InstanceRaw::ATTRIBUTES),
+            [(5, 0), (6, 16), (7, 32), (8, 48), (9, 64)]
+        );
+        assert!(InstanceRaw::ATTRIBUTES[..4]
+            .iter()
+            .all(|a| a.format == wgpu::VertexFormat::Float32x4));
+        assert_eq!(InstanceRaw::ATTRIBUTES[4].format, wgpu::VertexFormat::Uint32);
+    }
 }